@@ -0,0 +1,73 @@
+//! Removal of the terminal escape sequences that programs running under a PTY emit.
+
+#[derive(Clone, Copy, PartialEq, Default)]
+enum State {
+    #[default]
+    Text,
+    Escape,
+    Csi,
+    Osc,
+    OscEscape,
+}
+
+/// Strips CSI and OSC sequences from a byte stream. The state is kept between
+/// calls, so sequences split across chunks are still removed.
+#[derive(Default)]
+pub struct AnsiStripper {
+    state: State,
+}
+
+impl AnsiStripper {
+    pub fn strip(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len());
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Text, 0x1b) => State::Escape,
+                (State::Text, _) => {
+                    output.push(byte);
+                    State::Text
+                }
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']') => State::Osc,
+                // Any other escape is a two-byte sequence such as ESC = or ESC 7.
+                (State::Escape, _) => State::Text,
+                (State::Csi, 0x40..=0x7e) => State::Text,
+                (State::Csi, _) => State::Csi,
+                (State::Osc, 0x07) => State::Text,
+                (State::Osc, 0x1b) => State::OscEscape,
+                (State::Osc, _) => State::Osc,
+                (State::OscEscape, b'\\') => State::Text,
+                (State::OscEscape, _) => State::Osc,
+            };
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_csi_and_osc_sequences() {
+        let mut stripper = AnsiStripper::default();
+        let input = b"\x1b[1;32mgreen\x1b[0m \x1b]0;title\x07plain \x1b]8;;url\x1b\\link";
+        assert_eq!(stripper.strip(input), b"green plain link");
+    }
+
+    #[test]
+    fn drops_two_byte_escapes() {
+        let mut stripper = AnsiStripper::default();
+        assert_eq!(stripper.strip(b"a\x1b=b\x1b7c"), b"abc");
+    }
+
+    #[test]
+    fn strips_sequences_split_across_chunks() {
+        let mut stripper = AnsiStripper::default();
+        let mut output = stripper.strip(b"one\x1b[3");
+        output.extend(stripper.strip(b"1mtwo\x1b]0;ti"));
+        output.extend(stripper.strip(b"tle\x1b"));
+        output.extend(stripper.strip(b"\\three"));
+        assert_eq!(output, b"onetwothree");
+    }
+}
@@ -0,0 +1,294 @@
+//! Interactive sessions that connect the local terminal to a running daemon.
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::Result;
+
+use crate::{
+    DaemonPaths, FIFO_OPEN_TIMEOUT, FifoAccess, ensure_process_is_running, follow_fifo,
+    open_fifo_with_timeout, parse_key, resize_daemon, terminal_size,
+};
+
+/// The keys that end an attach session without touching the daemon, unless
+/// `--detach-keys` names others.
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-\\";
+
+/// A sequence of keys that ends an attach session, such as `ctrl-p,ctrl-q`.
+#[derive(Clone)]
+pub struct DetachKeys {
+    /// The keys as they were given, for telling the user.
+    pub spec: String,
+    bytes: Vec<u8>,
+}
+
+/// Parses a comma-separated list of key names, as `attyvo key` takes them.
+pub fn parse_detach_keys(s: &str) -> Result<DetachKeys, String> {
+    let mut bytes = Vec::new();
+    for key in s.split(',') {
+        bytes.extend(parse_key(key.trim())?);
+    }
+    Ok(DetachKeys {
+        spec: s.to_string(),
+        bytes,
+    })
+}
+
+/// Spots the detach keys in the keyboard input, holding back a partial match
+/// until it is known whether the rest of the sequence follows.
+struct DetachMatcher<'a> {
+    keys: &'a [u8],
+    matched: usize,
+    /// For each length of partial match, the longest shorter one that ends it,
+    /// so that e.g. `aab` is still found in `aaab`.
+    fallback: Vec<usize>,
+}
+
+impl<'a> DetachMatcher<'a> {
+    fn new(keys: &'a [u8]) -> Self {
+        let mut fallback = vec![0; keys.len()];
+        let mut len = 0;
+        for i in 1..keys.len() {
+            while len > 0 && keys[i] != keys[len] {
+                len = fallback[len - 1];
+            }
+            if keys[i] == keys[len] {
+                len += 1;
+            }
+            fallback[i] = len;
+        }
+        DetachMatcher {
+            keys,
+            matched: 0,
+            fallback,
+        }
+    }
+
+    /// Appends what should go to the daemon from `input` to `out`, returning
+    /// true once the whole sequence has been typed.
+    fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) -> bool {
+        for &byte in input {
+            let mut matched = self.matched;
+            while matched > 0 && byte != self.keys[matched] {
+                matched = self.fallback[matched - 1];
+            }
+            // What was held back and no longer starts a match is ordinary input.
+            out.extend_from_slice(&self.keys[..self.matched - matched]);
+            if byte == self.keys[matched] {
+                matched += 1;
+            } else {
+                out.push(byte);
+            }
+            self.matched = matched;
+            if matched == self.keys.len() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// How an attach session ended.
+pub enum AttachEnd {
+    Detached,
+    DaemonExited,
+}
+
+/// Puts the local terminal into raw mode for as long as it is alive.
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> Option<RawMode> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return None;
+        }
+        let mut raw = original;
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+        }
+        Some(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Set by the SIGWINCH handler when the local terminal has been resized.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+/// Catches SIGWINCH for as long as it is alive, putting back the previous
+/// handler afterwards.
+struct ResizeWatch {
+    previous: libc::sighandler_t,
+}
+
+impl ResizeWatch {
+    fn install() -> ResizeWatch {
+        let handler = on_sigwinch as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let previous = unsafe { libc::signal(libc::SIGWINCH, handler) };
+        ResizeWatch { previous }
+    }
+}
+
+impl Drop for ResizeWatch {
+    fn drop(&mut self) {
+        unsafe { libc::signal(libc::SIGWINCH, self.previous) };
+    }
+}
+
+/// Gives the daemon's PTY the size of the local terminal, if it has one.
+fn sync_size(dir: &Path, name: &str) {
+    if let Some((rows, cols)) = terminal_size() {
+        resize_daemon(dir, name, rows, cols).ok();
+    }
+}
+
+/// Forwards keystrokes to the daemon's stdin and its stdout to the screen until
+/// the detach keys are typed or the daemon exits. The daemon's PTY follows the
+/// size of the local terminal meanwhile.
+pub fn attach(dir: &Path, name: &str, detach_keys: &DetachKeys) -> Result<AttachEnd> {
+    ensure_process_is_running(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let mut daemon_stdin =
+        open_fifo_with_timeout(name, &paths.stdin, FifoAccess::Write, FIFO_OPEN_TIMEOUT)?;
+
+    let finished = Arc::new(AtomicBool::new(false));
+    {
+        let (dir, name, stdout_path) = (dir.to_path_buf(), name.to_string(), paths.stdout.clone());
+        let finished = Arc::clone(&finished);
+        std::thread::spawn(move || {
+            let mut screen = std::io::stdout();
+            follow_fifo(&dir, &name, &stdout_path, |output| {
+                screen.write_all(output)?;
+                screen.flush()?;
+                Ok(())
+            })
+            .ok();
+            finished.store(true, Ordering::SeqCst);
+        });
+    }
+
+    let _raw_mode = RawMode::enable();
+    let _resize_watch = ResizeWatch::install();
+    sync_size(dir, name);
+    let mut keyboard = std::io::stdin();
+    let mut buf = [0u8; 1024];
+    let mut matcher = DetachMatcher::new(&detach_keys.bytes);
+    let mut input = Vec::new();
+    while !finished.load(Ordering::SeqCst) {
+        // A resize interrupts the poll below, so it is picked up right away.
+        if RESIZED.swap(false, Ordering::SeqCst) {
+            sync_size(dir, name);
+        }
+        if !stdin_ready(100) {
+            continue;
+        }
+        let n = keyboard.read(&mut buf)?;
+        if n == 0 {
+            return Ok(AttachEnd::Detached);
+        }
+        input.clear();
+        if matcher.feed(&buf[..n], &mut input) {
+            daemon_stdin.write_all(&input)?;
+            return Ok(AttachEnd::Detached);
+        }
+        if daemon_stdin.write_all(&input).is_err() {
+            break;
+        }
+    }
+    Ok(AttachEnd::DaemonExited)
+}
+
+/// Waits up to `timeout_ms` for our own stdin to become readable.
+fn stdin_ready(timeout_ms: i32) -> bool {
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, timeout_ms) > 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a matcher for `keys` passes on from `chunks`, and whether it detached.
+    fn feed_all(keys: &str, chunks: &[&[u8]]) -> (Vec<u8>, bool) {
+        let keys = parse_detach_keys(keys).unwrap();
+        let mut matcher = DetachMatcher::new(&keys.bytes);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            if matcher.feed(chunk, &mut out) {
+                return (out, true);
+            }
+        }
+        (out, false)
+    }
+
+    #[test]
+    fn parses_detach_keys() {
+        let keys = parse_detach_keys("ctrl-p, ctrl-q").unwrap();
+        assert_eq!(keys.spec, "ctrl-p, ctrl-q");
+        assert_eq!(keys.bytes, [0x10, 0x11]);
+        assert_eq!(
+            parse_detach_keys(DEFAULT_DETACH_KEYS).unwrap().bytes,
+            [0x1c]
+        );
+        assert!(parse_detach_keys("ctrl-p,nope").is_err());
+    }
+
+    #[test]
+    fn detaches_on_the_whole_sequence() {
+        assert_eq!(
+            feed_all("ctrl-p,ctrl-q", &[b"ls\r\x10\x11rest"]),
+            (b"ls\r".to_vec(), true)
+        );
+        assert_eq!(
+            feed_all("ctrl-p,ctrl-q", &[b"a\x10", b"\x11"]),
+            (b"a".to_vec(), true)
+        );
+    }
+
+    #[test]
+    fn passes_on_a_partial_sequence() {
+        assert_eq!(
+            feed_all("ctrl-p,ctrl-q", &[b"\x10", b"x"]),
+            (b"\x10x".to_vec(), false)
+        );
+        assert_eq!(
+            feed_all("ctrl-p,ctrl-q", &[b"\x10\x10\x11"]),
+            (b"\x10".to_vec(), true)
+        );
+    }
+
+    #[test]
+    fn detaches_when_a_partial_sequence_overlaps_the_whole_one() {
+        assert_eq!(feed_all("a,a,b", &[b"aaab"]), (b"a".to_vec(), true));
+        assert_eq!(
+            feed_all("a,a,b", &[b"xa", b"a", b"ab"]),
+            (b"xa".to_vec(), true)
+        );
+        assert_eq!(feed_all("a,b,a,c", &[b"ababac"]), (b"ab".to_vec(), true));
+        assert_eq!(feed_all("a,a,b", &[b"aaa"]), (b"a".to_vec(), false));
+    }
+}
@@ -0,0 +1,2290 @@
+//! The `attyvo` command line.
+
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::{
+    CompleteEnv,
+    engine::{ArgValueCandidates, CompletionCandidate},
+    env::Shells,
+};
+
+use crate::{
+    BatchOutcome, DEFAULT_CONSUMER, DEFAULT_KILL_TIMEOUT, DEFAULT_PIPE_DIR, DEFAULT_READY_TIMEOUT,
+    DEFAULT_TERM, DaemonMeta, DaemonPaths, DaemonStatus, KillOutcome, LogPolicy, RestartPolicy,
+    STATUS_STDERR_LINES, StdinMode, WAIT_TIMEOUT_EXIT_CODE, ansi, attach, clean_daemons,
+    clone_daemon, config, create, daemon_status, daemon_statuses, decode_base64, default_cols,
+    default_health_interval, default_health_retries, default_restart_delay, default_restart_max,
+    default_rows, doctor, effective_term, ensure_pid_file, ensure_pipe_dir_exists,
+    ensure_process_is_running, envfile, error, error::Failure, events, exec, find_daemons,
+    find_duplicates, follow_stderr, format_duration, format_timestamp, glob_match,
+    kill_all_daemons, kill_daemon, last_lines, list_daemon_statuses, list_daemons, logging,
+    parse_key, parse_signal, pause_daemon, pipe, procfs, prune_daemons, read_as_consumer,
+    read_logs, read_stderr, read_stdout, read_stdout_log, rename_daemon, resize_daemon,
+    resolve_cwd, restart_daemon, resume_daemon, run_foreground, send, send_eof, send_interrupt,
+    signal_all_daemons, signal_daemon, stderr_tail, terminal_size, unescape, unix_now,
+    validate_consumer, validate_name, wait_daemon, wait_for_output, wait_for_stdout, watch, write,
+    write_file,
+};
+
+/// Prints a confirmation message unless `--quiet` was given.
+macro_rules! say {
+    ($quiet:expr, $($arg:tt)*) => {
+        if !$quiet {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Starts every daemon in the config file that is not already running, each
+/// one only after the daemons it depends on are up.
+fn compose_up(dir: &Path, file: &Path, quiet: bool) -> Result<()> {
+    let mut config = config::load(file)?;
+    let order: Vec<String> = config.start_order()?.into_iter().map(str::to_string).collect();
+    let mut failed: Vec<String> = Vec::new();
+    for name in order {
+        validate_name(&name).map_err(anyhow::Error::msg)?;
+        let service = config.daemons.remove(&name).expect("ordered names come from the config");
+        if ensure_process_is_running(dir, &name).is_ok() {
+            say!(quiet, "Daemon '{}' is already running", name);
+            continue;
+        }
+        if let Some(dependency) = service.depends_on.iter().find(|dependency| failed.contains(dependency)) {
+            eprintln!("Skipping daemon '{}': its dependency '{}' did not start", name, dependency);
+            failed.push(name);
+            continue;
+        }
+        let (default_rows, default_cols) = terminal_size().unwrap_or((default_rows(), default_cols()));
+        let meta = DaemonMeta {
+            rows: service.rows.unwrap_or(default_rows),
+            cols: service.cols.unwrap_or(default_cols),
+            term: Some(effective_term(&service.env)),
+            env: service.env,
+            env_clear: service.env_clear,
+            cwd: service.cwd.map(|cwd| resolve_cwd(&cwd)).transpose()?,
+            restart: service.restart.unwrap_or_default(),
+            on_exit: service.on_exit,
+            ..DaemonMeta::new(service.command, service.args)
+        };
+        match create(dir, &name, &meta, Some(DEFAULT_READY_TIMEOUT)) {
+            Ok(()) => say!(quiet, "Daemon '{}' created and started", name),
+            Err(e) => {
+                eprintln!("Failed to start daemon '{}': {}", name, e);
+                failed.push(name);
+            }
+        }
+    }
+    if !failed.is_empty() {
+        return Err(anyhow::anyhow!("{} daemon(s) failed to start", failed.len()));
+    }
+    Ok(())
+}
+
+/// Kills every daemon in the config file that exists, dependents first.
+fn compose_down(dir: &Path, file: &Path, grace: Duration, quiet: bool) -> Result<()> {
+    let config = config::load(file)?;
+    for name in config.start_order()?.into_iter().rev() {
+        validate_name(name).map_err(anyhow::Error::msg)?;
+        if ensure_pid_file(dir, name).is_err() {
+            continue;
+        }
+        match kill_daemon(dir, name, grace, false, false)? {
+            KillOutcome::AlreadyStopped => say!(quiet, "Daemon '{}' was not running; cleaned up", name),
+            KillOutcome::Graceful => say!(quiet, "Daemon '{}' killed", name),
+            KillOutcome::Forced => say!(quiet, 
+                "Daemon '{}' ignored SIGTERM for {}s and was killed with SIGKILL",
+                name,
+                grace.as_secs()
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Shows how `create` would start daemon `name` from `meta`, for `--dry-run`.
+fn print_create_plan(dir: &Path, name: &str, meta: &DaemonMeta, json: bool) -> Result<()> {
+    let paths = DaemonPaths::new(dir, name);
+    if json {
+        let files: serde_json::Map<_, _> = paths
+            .labelled()
+            .into_iter()
+            .map(|(label, path)| (label.to_string(), path.display().to_string().into()))
+            .collect();
+        let plan = serde_json::json!({ "name": name, "meta": meta, "files": files });
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        return Ok(());
+    }
+    println!("Name:    {}", name);
+    println!("Command: {}", meta.command_line());
+    if let Some(shell) = &meta.shell {
+        println!("Shell:   {} -c", shell);
+    }
+    print_size(meta);
+    if let Some(term) = &meta.term {
+        println!("Term:    {}", term);
+    }
+    match &meta.cwd {
+        Some(cwd) => println!("Cwd:     {}", cwd.display()),
+        None => println!("Cwd:     {} (inherited)", std::env::current_dir()?.display()),
+    }
+    if meta.env_clear {
+        println!("Env:     (cleared)");
+    }
+    for (key, value) in &meta.env {
+        println!("Env:     {}={}", key, value);
+    }
+    print_env_file_keys(meta);
+    if meta.uid.is_some() || meta.gid.is_some() {
+        let id = |id: Option<u32>| id.map_or("unchanged".to_string(), |id| id.to_string());
+        println!("User:    uid {}, gid {}", id(meta.uid), id(meta.gid));
+    }
+    if let Some(policy) = clap::ValueEnum::to_possible_value(&meta.restart)
+        && meta.restart != RestartPolicy::No
+    {
+        println!(
+            "Restart: {} (after {}s, at most {} times)",
+            policy.get_name(),
+            meta.restart_delay,
+            meta.restart_max
+        );
+    }
+    if let Some(hook) = &meta.on_exit {
+        println!("On exit: {}", hook);
+    }
+    if let Some(check) = &meta.health_cmd {
+        println!("Health:  {} (every {}s)", check, meta.health_interval);
+    }
+    if let Some(timeout) = meta.timeout {
+        println!("Timeout: {}", format_duration(timeout));
+    }
+    if let Some(idle_timeout) = meta.idle_timeout {
+        println!("Idle:    shut down after {} without input", format_duration(idle_timeout));
+    }
+    if meta.log_policy == LogPolicy::Truncate {
+        println!("Log:     emptied on every (re)start");
+    }
+    if meta.stdin_mode == StdinMode::Char {
+        println!("Stdin:   byte by byte");
+    }
+    let limits: Vec<String> = [
+        meta.limit_as.map(|bytes| format!("{} bytes of memory", bytes)),
+        meta.limit_cpu.map(|secs| format!("{} of CPU time", format_duration(secs))),
+        meta.limit_nofile.map(|files| format!("{} open files", files)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !limits.is_empty() {
+        println!("Limits:  {}", limits.join(", "));
+    }
+    if ensure_process_is_running(dir, name).is_ok() {
+        println!("Note:    a daemon named '{}' is already running; create would fail", name);
+    }
+    println!("Files:");
+    for (label, path) in paths.labelled() {
+        println!("  {:<11} {}", label, path.display());
+    }
+    Ok(())
+}
+
+#[derive(Parser)]
+#[command(name = "attyvo")]
+#[command(about = "A PTY-based daemon process manager with named pipe I/O", long_about = "
+attyvo manages long-running processes as daemons with pseudo-terminal (PTY) support.
+Each daemon runs in the background with stdin, stdout, and stderr accessible through
+named pipes, allowing interactive communication with terminal-aware applications.
+
+Examples:
+  # Start a Python REPL daemon
+  attyvo create python-repl python3 -i
+
+  # Send commands to the daemon
+  attyvo write python-repl \"print('Hello from daemon!')\"
+
+  # Read output from the daemon
+  attyvo read python-repl
+
+  # Resize the daemon's terminal
+  attyvo resize python-repl 50 200
+
+  # Check whether a daemon is still alive
+  attyvo status python-repl
+
+  # List all daemons and their state
+  attyvo list
+
+  # Restart a daemon with its original command
+  attyvo restart python-repl
+
+  # Stop a specific daemon
+  attyvo kill python-repl
+
+  # Stop all running daemons
+  attyvo kill-all
+
+Exit status:
+  0    success
+  1    any failure not listed below
+  2    invalid command line
+  3    no daemon of that name exists
+  4    the daemon's command is not running
+  5    a daemon of that name already exists
+  6    permission denied
+  7    read --require-output found no output
+  124  timed out waiting
+  126  the command is not executable
+  127  the command was not found
+")]
+struct Cli {
+    /// Emit machine-readable JSON instead of human-readable text
+    #[arg(long, global = true)]
+    json: bool,
+    /// Don't print confirmation messages; command output and errors are still shown
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Print debug logs to stderr (-vv for more); daemons write theirs to NAME.attyvo.log.
+    /// Without it, RUST_LOG may name a level such as debug
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Directory holding the daemons' pipes and PID files
+    #[arg(long, global = true, env = "ATTYVO_DIR", default_value = DEFAULT_PIPE_DIR)]
+    dir: PathBuf,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Commands {
+    /// Create and start a new daemon process with PTY support
+    #[command(long_about = "Creates a new daemon that runs in the background with a pseudo-terminal.
+The daemon's I/O streams are accessible through named pipes in the pipe directory
+(/tmp/daemon_pipes/ unless overridden with --dir or ATTYVO_DIR).
+
+With --auto-name the name is left out and the first free one of <prefix>-1,
+<prefix>-2, ... is used instead, the prefix being the command's name unless
+--name-prefix says otherwise. The chosen name is printed on stdout:
+
+  name=$(attyvo create --auto-name python3 -i)
+
+If a running daemon already runs the same command with the same arguments in
+the same directory, a warning naming it is printed, since starting a service
+twice is usually a mistake; --allow-duplicate leaves it out.")]
+    Create {
+        #[command(flatten)]
+        options: DaemonOptions,
+        /// Only return once the daemon's command is confirmed to be running
+        #[arg(long)]
+        wait_ready: bool,
+        /// Print what would be started and where its files would go, without starting it
+        #[arg(long)]
+        dry_run: bool,
+        /// How long --wait-ready waits before giving up
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_READY_TIMEOUT.as_secs(), requires = "wait_ready")]
+        ready_timeout: u64,
+        /// Leave out the name and have a free one picked, printing it on stdout
+        #[arg(long)]
+        auto_name: bool,
+        /// Do not warn when a running daemon already runs the same command in the same directory
+        #[arg(long)]
+        allow_duplicate: bool,
+        /// Start picked names with this instead of the command's name; implies --auto-name
+        #[arg(long, value_name = "PREFIX")]
+        name_prefix: Option<String>,
+        /// Unique identifier for this daemon (left out with --auto-name)
+        name: String,
+        /// Command or executable to run as a daemon
+        #[arg(required_unless_present_any = ["auto_name", "name_prefix"], allow_hyphen_values = true)]
+        command: Option<String>,
+        /// Additional arguments to pass to the command
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Run a daemon in the foreground, for supervisors such as systemd or docker
+    #[command(long_about = "Does what `create` does, with the same options, but stays in the foreground
+instead of detaching: this process is the daemon until its command exits for
+good, and then exits with the command's exit code. The daemon's pipes work as
+usual, so other attyvo commands can talk to it meanwhile, and its output is also
+copied to this process's stdout and stderr for the supervisor to collect.
+SIGTERM, SIGINT and SIGHUP are passed on to the command, which is then not
+restarted any more. Debug logging goes to stderr rather than NAME.attyvo.log.")]
+    Run {
+        #[command(flatten)]
+        options: DaemonOptions,
+        /// Unique identifier for this daemon
+        #[arg(value_parser = validate_name)]
+        name: String,
+        /// Command or executable to run
+        command: String,
+        /// Additional arguments to pass to the command
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Run a command under a PTY in the foreground and exit with its exit code
+    #[command(long_about = "Runs a command inside a throwaway daemon, connecting attyvo's own stdin,
+stdout and stderr to it until the command exits. The daemon's pipes, PID file and
+logs are removed afterwards and attyvo exits with the command's exit code, so
+`attyvo exec` works as a plain PTY wrapper, e.g. `attyvo exec -- ls --color=auto`.")]
+    Exec {
+        /// Command to run
+        command: String,
+        /// Arguments for the command
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Send input to a daemon's stdin
+    #[command(long_about = "Writes a message to the daemon's stdin pipe, allowing you to send
+commands or data to interactive programs running as daemons.")]
+    Write {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Text to send; several messages are written as separate lines, in order
+        /// (a newline will be added automatically)
+        #[arg(required = true, value_name = "MESSAGE")]
+        messages: Vec<String>,
+        /// Don't append a newline after the last message
+        #[arg(short = 'n', long)]
+        no_newline: bool,
+        /// Interpret backslash escapes such as \t, \r, \e and \x1b in the messages
+        #[arg(long)]
+        raw: bool,
+        /// Decode the messages from base64 and write exactly those bytes, with no newlines
+        #[arg(long, conflicts_with_all = ["raw", "no_newline"])]
+        base64: bool,
+    },
+    /// Write a message to a daemon and print the output it produces in response
+    #[command(long_about = "Writes a message to the daemon's stdin like `write`, then reads its stdout
+until the response is complete and prints it. Output already waiting in the
+pipe is discarded first, so only what follows the message is returned.
+
+The response ends after --idle milliseconds without new output (500 by default),
+or as soon as it matches --until, e.g. a REPL prompt:
+
+  attyvo send python-repl '2 + 2' --until '>>> $'
+
+If --timeout passes first, what was read so far is printed and attyvo exits with
+code 124.")]
+    Send {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Text to send (a newline will be added automatically)
+        message: String,
+        /// Don't append a newline after the message
+        #[arg(short = 'n', long)]
+        no_newline: bool,
+        /// Interpret backslash escapes such as \t, \r, \e and \x1b in the message
+        #[arg(long)]
+        raw: bool,
+        /// End the response after this many milliseconds without new output
+        /// (without --until, defaults to 500)
+        #[arg(long, value_name = "MS")]
+        idle: Option<u64>,
+        /// End the response as soon as it matches this regular expression
+        #[arg(long, value_name = "REGEX")]
+        until: Option<regex::Regex>,
+        /// Give up after this many seconds
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Send the contents of a file, or of attyvo's own stdin, to a daemon's stdin
+    #[command(name = "write-file", long_about = "Streams a file into the daemon's stdin pipe without appending a newline.
+Use - as the path to forward attyvo's own stdin, e.g. `cat setup.py | attyvo write-file repl -`.")]
+    WriteFile {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// File to send, or - for stdin
+        path: PathBuf,
+        /// Decode the contents from base64 and send the bytes they encode
+        #[arg(long)]
+        base64: bool,
+    },
+    /// Signal end-of-file on a daemon's stdin
+    #[command(long_about = "Makes the daemon's command see end-of-file on its stdin, after any input
+written before it, so programs like sort or wc can finish. This types the terminal's
+EOF character (usually ^D), so it only takes effect for programs reading in line mode.")]
+    Eof {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+    },
+    /// Interrupt a daemon's command as if Ctrl-C was pressed
+    #[command(long_about = "Types the terminal's interrupt character (usually ^C) after any input written
+before it, so the foreground process in the daemon gets SIGINT just as it would
+from a keyboard. Programs in raw mode receive the character as input instead.")]
+    Interrupt {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+    },
+    /// Press named keys such as ctrl-c, enter, tab, esc or up in a daemon
+    #[command(long_about = "Writes the bytes a terminal sends for each named key to the daemon's stdin, in
+order. Keys are enter, tab, esc, space, backspace, delete, insert, home, end,
+pageup, pagedown, up, down, left, right, f1 to f12, and ctrl- followed by a letter
+or one of @ [ \\ ] ^ _ (also written as ^C). Names are case-insensitive, e.g.
+
+  attyvo key vim-session esc : w q enter
+
+where single characters such as : stand for themselves.")]
+    Key {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Keys to press, in order
+        #[arg(required = true, value_name = "KEY", value_parser = parse_key)]
+        keys: Vec<Vec<u8>>,
+    },
+    /// Attach the local terminal to a daemon for an interactive session
+    #[command(long_about = "Puts the local terminal into raw mode, forwards every keystroke to the daemon's
+stdin and streams its stdout to the screen. Press Ctrl-\\ to detach; the daemon keeps
+running. The terminal is restored when the session ends, even if the daemon exits.
+
+If the program inside needs Ctrl-\\ itself, --detach-keys picks another sequence
+of keys, named as for `attyvo key` and separated by commas, e.g. ctrl-p,ctrl-q.
+Keys typed as part of the sequence are only passed on if the sequence breaks off.
+
+While attached, the daemon's terminal takes the size of the local one and follows
+it when it is resized, so full-screen programs redraw to fit.")]
+    Attach {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Keys that detach, separated by commas, e.g. ctrl-p,ctrl-q
+        #[arg(long, value_name = "KEYS", value_parser = attach::parse_detach_keys, default_value = attach::DEFAULT_DETACH_KEYS)]
+        detach_keys: attach::DetachKeys,
+    },
+    /// Read error output from a daemon's stderr
+    #[command(name = "read-stderr", long_about = "Reads all available output from the daemon's stderr pipe.
+This is a non-blocking read that returns immediately with any buffered output,
+unless --follow is given, in which case new output is streamed as it arrives.")]
+    ReadStderr {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Keep streaming new output as it arrives, until interrupted
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Read output from a daemon's stdout
+    #[command(long_about = "Reads the daemon's stdout that has not been read yet, as kept in its log.
+This is a non-blocking read that returns immediately with any waiting output,
+unless --follow is given, in which case new output is streamed as it arrives.
+With --wait, the read waits until the first new output shows up.
+ANSI escape sequences are removed when stdout is not a terminal; use
+--strip-ansi or --no-strip-ansi to choose explicitly.
+
+Every reader keeps its own position in the log, named by --consumer ID or the
+ATTYVO_CONSUMER environment variable and \"default\" otherwise. A read continues
+where the previous read with the same ID stopped, so output is never seen twice,
+even across restarts, and readers with different IDs each get all of it without
+taking anything from one another. Output that was rotated out of the log before
+it was read is skipped.
+
+With --clear, the waiting output is thrown away instead of printed, and only how
+many bytes that was is reported, so that the next read starts from a clean
+slate. With --combined, the stderr pipe is drained too; unlike stdout, it is
+shared by all readers.")]
+    Read {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Keep streaming new output as it arrives, until interrupted
+        #[arg(short, long)]
+        follow: bool,
+        /// Print the daemon's retained output history from its log instead of the pipe
+        #[arg(long, conflicts_with = "follow")]
+        from_start: bool,
+        /// Remove ANSI escape sequences (the default when stdout is not a terminal,
+        /// unless the daemon runs with --no-pty)
+        #[arg(long, conflicts_with = "no_strip_ansi")]
+        strip_ansi: bool,
+        /// Keep ANSI escape sequences (the default when stdout is a terminal)
+        #[arg(long)]
+        no_strip_ansi: bool,
+        /// Replace invalid UTF-8 with U+FFFD instead of passing the raw bytes through
+        #[arg(long)]
+        lossy: bool,
+        /// Stop after N bytes and leave the rest for the next read
+        #[arg(long, value_name = "N", conflicts_with_all = ["follow", "from_start"])]
+        max_bytes: Option<u64>,
+        /// Block until there is some output to return instead of returning nothing
+        #[arg(short, long, conflicts_with_all = ["follow", "from_start"])]
+        wait: bool,
+        /// Print the last N lines of the daemon's output from its log, then stream
+        /// new output if --follow is given
+        #[arg(short = 'n', long, value_name = "N", conflicts_with_all = ["from_start", "max_bytes", "wait"])]
+        lines: Option<usize>,
+        /// With --wait, give up after this many seconds and exit with code 124
+        #[arg(long, value_name = "SECS", requires = "wait")]
+        timeout: Option<u64>,
+        /// Exit with code 7 if there was no output to return
+        #[arg(long, conflicts_with = "follow")]
+        require_output: bool,
+        /// Also drain the stderr pipe, printing its output after stdout's
+        #[arg(long, conflicts_with_all = ["follow", "from_start", "max_bytes", "wait", "lines"])]
+        combined: bool,
+        /// Continue where the previous read with this ID stopped
+        #[arg(long, value_name = "ID", env = "ATTYVO_CONSUMER", value_parser = validate_consumer, default_value = DEFAULT_CONSUMER)]
+        consumer: String,
+        /// Discard the waiting output instead of printing it, reporting how many bytes that was
+        #[arg(long, conflicts_with_all = ["follow", "from_start", "max_bytes", "wait", "lines", "require_output"])]
+        clear: bool,
+    },
+    /// Show a daemon's recent stdout without taking it from the pipe
+    #[command(long_about = "Prints the daemon's stdout as retained by its log, leaving the pipe alone so
+that a later `read` still gets the same output. ANSI escape sequences are
+removed when stdout is not a terminal, as with `read`.")]
+    Peek {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Only print the last N lines
+        #[arg(short = 'n', long, value_name = "N")]
+        lines: Option<usize>,
+        /// Keep ANSI escape sequences even when stdout is not a terminal
+        #[arg(long)]
+        no_strip_ansi: bool,
+    },
+    /// Show a daemon's stdout and stderr interleaved in the order they were written
+    #[command(long_about = "Prints the lines the daemon wrote to stdout and stderr, merged in the order
+they happened and prefixed with [out] or [err]. The history comes from the
+daemon's log, so it covers output nobody was reading at the time.")]
+    Logs {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Keep printing new lines as they are logged, until interrupted
+        #[arg(short, long)]
+        follow: bool,
+        /// Only print the last N lines of the history
+        #[arg(short = 'n', long, value_name = "N")]
+        lines: Option<usize>,
+        /// Prefix every line with the time it was logged
+        #[arg(short, long)]
+        timestamps: bool,
+    },
+    /// Check that daemons can run here, with hints for fixing what is wrong
+    #[command(long_about = "Checks the pipe directory's ownership and permissions, that named pipes, locks
+and pseudo-terminals work in it, whether /proc is available, and whether dead
+daemons or orphaned pipes are lying around. Each problem comes with a hint on how
+to fix it. Exits with status 1 if any check failed; warnings do not count.")]
+    Doctor,
+    /// Give a daemon a new name without restarting it
+    #[command(long_about = "Renames all of the daemon's pipes, its PID file, metadata and logs. A running
+daemon keeps running and carries on under the new name.")]
+    Rename {
+        /// Current name of the daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        old: String,
+        /// Name to move the daemon to; must not be in use
+        #[arg(value_parser = validate_name)]
+        new: String,
+    },
+    /// Create a new daemon with the same settings as an existing one
+    #[command(visible_alias = "copy", long_about = "Starts daemon DST with the command, arguments, environment, working directory,
+PTY size and every other setting of daemon SRC, which may be running or not. The
+copy gets its own pipes and logs and is confirmed running before clone returns.
+
+--arg-override replaces one argument, counting from 1 after the command, and
+--env adds or changes an environment variable, e.g. to run a second server:
+
+  attyvo clone web web-2 --arg-override 2=8081
+
+DST must not be in use, not even by the leftover files of a dead daemon.")]
+    Clone {
+        /// Daemon to copy the settings of
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        src: String,
+        /// Name of the new daemon
+        #[arg(value_parser = validate_name)]
+        dst: String,
+        /// Replace argument N (counting from 1) with VALUE; may be repeated
+        #[arg(long = "arg-override", value_name = "N=VALUE", value_parser = parse_arg_override)]
+        arg_overrides: Vec<(usize, String)>,
+        /// Set an environment variable for the new daemon; may be repeated
+        #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_pair)]
+        env: Vec<(String, String)>,
+    },
+    /// Feed everything one daemon prints into another daemon's input
+    #[command(long_about = "Starts a small background relay that copies the stdout SRC produces from now on
+into the stdin of DST, like a shell pipeline that outlives the shell, e.g.
+
+  attyvo pipe producer filter && attyvo pipe filter consumer
+
+The relay reads SRC's stdout log, so `read` and other consumers still see all of
+its output. It keeps going while the two daemons restart their commands and
+ends by itself once either of them is gone. --stop tears it down earlier and
+--list shows the relays that are running.")]
+    Pipe {
+        /// Stop the relay from SRC into DST instead of starting one
+        #[arg(long, conflicts_with = "list")]
+        stop: bool,
+        /// List the running relays instead
+        #[arg(long, conflicts_with_all = ["src", "dst"])]
+        list: bool,
+        /// Daemon whose output is relayed
+        #[arg(
+            required_unless_present = "list",
+            value_parser = validate_name,
+            add = ArgValueCandidates::new(daemon_name_candidates)
+        )]
+        src: Option<String>,
+        /// Daemon whose input receives it
+        #[arg(
+            required_unless_present = "list",
+            value_parser = validate_name,
+            add = ArgValueCandidates::new(daemon_name_candidates)
+        )]
+        dst: Option<String>,
+    },
+    /// Change the PTY dimensions of a running daemon
+    #[command(long_about = "Resizes the daemon's pseudo-terminal to the given number of rows and columns.
+The program running inside the daemon receives SIGWINCH and can redraw itself.")]
+    Resize {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// New number of rows
+        rows: u16,
+        /// New number of columns
+        cols: u16,
+    },
+    /// Show the PID, state, command and uptime of a daemon
+    #[command(long_about = "Reports whether the daemon is running, along with its PID, the command it was
+started with and how long it has been up. On a terminal the state is colored as
+in `attyvo list`, unless NO_COLOR is set.")]
+    Status {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+    },
+    /// Print the paths of a daemon's pipes, PID file, metadata and logs
+    #[command(long_about = "Prints where the named pipes, PID file, metadata and logs of a daemon live in
+the pipe directory, for tools that talk to the pipes directly. The paths are
+printed whether or not the daemon exists.")]
+    Paths {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+    },
+    /// Block until a daemon's command exits and return its exit code
+    #[command(long_about = "Waits for the daemon's command to finish and exits with the same exit code.
+Commands killed by a signal produce 128 + the signal number. If --timeout elapses
+first, attyvo exits with code 124.")]
+    Wait {
+        /// Name of the daemon to wait for
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Give up after this many seconds
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Block until a line of a daemon's stdout matches a regular expression
+    #[command(name = "wait-output", long_about = "Watches the daemon's stdout log until a line matches PATTERN, then exits
+successfully, e.g. to wait for a server to come up:
+
+  attyvo wait-output web 'listening on'
+
+Output already in the log counts, and the pipe is left alone for `read`. Lines
+are matched with ANSI escape sequences removed. If --timeout elapses first,
+attyvo exits with code 124; if the daemon stops first, with an error.")]
+    WaitOutput {
+        /// Name of the daemon to watch
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Regular expression to look for in each line of output
+        pattern: regex::Regex,
+        /// Give up after this many seconds
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Send a signal to a daemon's command
+    #[command(long_about = "Sends a signal to the command running inside the daemon, e.g. HUP to make it
+reload its configuration. The daemon's pipes and PID file are left untouched.")]
+    Signal {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Signal name (HUP, INT, QUIT, KILL, USR1, USR2, TERM, ...) or number
+        #[arg(value_parser = parse_signal)]
+        signal: i32,
+    },
+    /// Freeze a daemon's command without stopping it
+    #[command(long_about = "Stops the daemon's command, and any processes it started, with SIGSTOP so that
+it uses no CPU until `resume`. Its pipes stay open and input written meanwhile
+is delivered once it carries on. `status` and `list` show the daemon as paused.")]
+    Pause {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+    },
+    /// Let a paused daemon's command carry on
+    #[command(long_about = "Continues a command frozen by `pause` with SIGCONT.")]
+    Resume {
+        /// Name of the target daemon
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+    },
+    /// Restart a daemon using the command it was originally created with
+    #[command(long_about = "Stops the daemon if it is still running, cleans up its PID file and pipes, and
+starts it again with the same command, arguments, environment and PTY size.")]
+    Restart {
+        /// Name of the daemon to restart
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+    },
+    /// Restart a daemon whenever watched files change
+    #[command(long_about = "Watches the given paths recursively and restarts the daemon, as `restart` would,
+whenever files under them change, e.g. to rerun a server on every edit:
+
+  attyvo watch web --path src --ext rs,toml
+
+Changes are collected until none have arrived for --debounce milliseconds, so a
+burst of saves causes a single restart. Changes inside the pipe directory are
+ignored. Runs in the foreground until interrupted.")]
+    Watch {
+        /// Name of the daemon to restart
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// File or directory to watch; may be repeated
+        #[arg(long = "path", value_name = "PATH", required = true)]
+        paths: Vec<PathBuf>,
+        /// Only react to files with these extensions, e.g. rs,toml
+        #[arg(long = "ext", value_name = "EXT", value_delimiter = ',')]
+        extensions: Vec<String>,
+        /// Milliseconds without further changes to wait before restarting
+        #[arg(long, value_name = "MS", default_value_t = watch::DEFAULT_DEBOUNCE.as_millis() as u64)]
+        debounce: u64,
+    },
+    /// Terminate a daemon and clean up its resources
+    #[command(long_about = "Stops the daemon process and removes its PID file and named pipes.
+The daemon will receive a SIGTERM signal for graceful shutdown, followed by SIGKILL
+if it is still running once the timeout expires.
+
+With --keep-logs the logs and metadata stay behind for a post-mortem, e.g. with
+`attyvo logs NAME`; `attyvo clean NAME` removes them later.")]
+    Kill {
+        /// Name of the daemon to terminate
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: String,
+        /// Seconds to wait after SIGTERM before sending SIGKILL
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_KILL_TIMEOUT.as_secs())]
+        timeout: u64,
+        /// Remove the daemon's files even if its PID file is missing or unreadable
+        #[arg(long)]
+        force: bool,
+        /// Only remove the PID file and pipes, keeping the logs, metadata and exit status
+        #[arg(long)]
+        keep_logs: bool,
+    },
+    /// Show CPU and memory use of running daemons, refreshed periodically
+    #[command(long_about = "Samples /proc for every running daemon's command and shows its CPU usage over
+the last interval and its resident memory, busiest first. The table refreshes
+until interrupted; use --once to print a single sample and exit.")]
+    Top {
+        /// Seconds between samples, at least 0.1
+        #[arg(long, value_name = "SECS", default_value = "2", value_parser = parse_interval)]
+        interval: Duration,
+        /// Print one sample and exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Start every daemon described in a config file
+    #[command(long_about = "Reads a TOML file with one [daemons.<name>] table per daemon, giving its
+command and optionally args, env, env_clear, cwd, rows, cols, restart, on_exit
+and depends_on, and creates each one that is not already running. Daemons are
+started after the ones they depend on, once those are confirmed running.")]
+    Up {
+        /// Config file describing the daemons
+        #[arg(short, long, default_value = "attyvo.toml")]
+        file: PathBuf,
+    },
+    /// Kill every daemon described in a config file
+    Down {
+        /// Config file describing the daemons
+        #[arg(short, long, default_value = "attyvo.toml")]
+        file: PathBuf,
+        /// Seconds to wait after SIGTERM before sending SIGKILL
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_KILL_TIMEOUT.as_secs())]
+        timeout: u64,
+    },
+    /// Display all known daemons with their PID, state and command
+    #[command(long_about = "Shows a table of all daemons by checking PID files in the pipe directory,
+including whether each one is still running. Only daemons with valid PID files are displayed.
+
+Daemons are listed by name unless --sort says otherwise; by uptime, the longest
+running come first, and daemons that are not running come last; by status,
+running daemons come first, then paused, restarting and stopped ones. --filter only
+shows names matching a glob, where `*` matches any run of characters and `?` a
+single one, e.g. `attyvo list --filter 'web-*'`.
+
+On a terminal the state is colored: green while running, yellow while paused
+or restarting and red once the command is gone. Set NO_COLOR to turn this off.")]
+    List {
+        /// Only show daemons whose command is still running
+        #[arg(long)]
+        running: bool,
+        /// Order the daemons by this
+        #[arg(long, value_enum, value_name = "KEY", default_value_t = ListOrder::Name)]
+        sort: ListOrder,
+        /// Only show daemons whose names match this glob pattern
+        #[arg(long, value_name = "PATTERN")]
+        filter: Option<String>,
+    },
+    /// Remove leftover PID files and pipes of daemons that are no longer running
+    #[command(long_about = "Checks every daemon in the pipe directory and removes the PID file and named
+pipes of those whose process has died, e.g. after a crash or a reboot.")]
+    Prune,
+    /// Remove the leftover logs, metadata and cursors of stopped daemons
+    #[command(long_about = "Removes every file left in the pipe directory by daemon NAME, or by every
+daemon that is no longer running when no NAME is given: logs, metadata, exit
+status and `read` cursors, such as those kept by `attyvo kill --keep-logs`.
+
+Daemons whose command is running, that are about to restart it or that are
+still being created are left alone, and so is every file they use. The space
+freed is reported for each daemon cleaned.")]
+    Clean {
+        /// Daemon to clean up after (every stopped daemon when omitted)
+        #[arg(value_parser = validate_name, add = ArgValueCandidates::new(daemon_name_candidates))]
+        name: Option<String>,
+    },
+    /// Terminate all running daemons
+    #[command(name = "kill-all", long_about = "Stops all running daemon processes and cleans up their resources.
+Each daemon will receive a SIGTERM signal for graceful shutdown, followed by SIGKILL
+if it is still running once the timeout expires.
+
+With a PATTERN only the daemons whose names match it are killed; `*` matches any
+run of characters and `?` a single one, e.g. `attyvo kill-all 'test-*'`.
+
+With --signal the given signal is sent to every running daemon's command instead,
+and the daemons are left running, e.g. `attyvo kill-all --signal HUP` to make
+them all reload their configuration.")]
+    KillAll {
+        /// Only kill daemons whose names match this glob pattern
+        #[arg(value_name = "PATTERN")]
+        filter: Option<String>,
+        /// Seconds to wait after SIGTERM before sending SIGKILL
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_KILL_TIMEOUT.as_secs())]
+        timeout: u64,
+        /// Send this signal (HUP, USR1, ... or a number) instead of killing the daemons
+        #[arg(long, value_name = "SIGNAL", value_parser = parse_signal, conflicts_with = "timeout")]
+        signal: Option<i32>,
+    },
+    /// Stream lifecycle events of all daemons as JSON lines
+    #[command(long_about = "Listens for lifecycle events in the pipe directory and prints every one of them
+as one JSON object per line, until interrupted. Events are `created`, `restarted`
+(with the new PID and restart count), `exited` (with the exit code or signal and
+whether the daemon gave up restarting) and `killed`.
+
+Other tools can receive the same stream by connecting to the Unix socket
+`events.sock` in the pipe directory, e.g.:
+
+  socat - UNIX-CONNECT:/tmp/daemon_pipes/events.sock
+
+A running daemon serves the socket, or `attyvo events` while no daemon runs.
+Every connected client gets every event. When the process serving the socket
+exits, another takes over and clients have to reconnect.")]
+    Events,
+    /// Start a daemon on behalf of `DaemonManager`, with settings as JSON on stdin
+    #[command(hide = true)]
+    Spawn {
+        #[arg(value_parser = validate_name)]
+        name: String,
+        /// Restart the daemon with the settings it was created with instead
+        #[arg(long)]
+        restart: bool,
+    },
+    /// Print a shell script that enables tab completion for attyvo
+    #[command(long_about = "Prints a completion script for the given shell. Subcommands and flags are
+completed, and so are the names of existing daemons in ATTYVO_DIR (or
+/tmp/daemon_pipes/). Load it from your shell's startup file, e.g.:
+
+  bash:  source <(attyvo completions bash)
+  zsh:   source <(attyvo completions zsh)
+  fish:  attyvo completions fish | source")]
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_parser = clap::builder::PossibleValuesParser::new(Shells::builtins().names()))]
+        shell: String,
+    },
+}
+
+/// What `list --sort` orders daemons by.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ListOrder {
+    Name,
+    Pid,
+    Uptime,
+    Status,
+}
+
+/// How `create` and `run` set up the daemon and its command.
+#[derive(Args)]
+struct DaemonOptions {
+    /// Number of PTY rows (defaults to the current terminal's height, or 24)
+    #[arg(long)]
+    rows: Option<u16>,
+    /// Number of PTY columns (defaults to the current terminal's width, or 80)
+    #[arg(long)]
+    cols: Option<u16>,
+    /// Set an environment variable for the command (repeatable); TERM defaults to
+    /// xterm-256color
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_pair)]
+    env: Vec<(String, String)>,
+    /// Load environment variables from a dotenv file, re-read at every start; --env
+    /// takes precedence, and only the variable names are stored
+    #[arg(long, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+    /// Start the command with an empty environment instead of inheriting attyvo's
+    #[arg(long)]
+    env_clear: bool,
+    /// Working directory for the command, relative to the current directory
+    #[arg(long)]
+    cwd: Option<PathBuf>,
+    /// Rotate the stdout and stderr logs once they exceed this size (e.g. 512K, 10M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "10M")]
+    log_max_size: u64,
+    /// Empty the stdout and stderr logs every time the command is started or restarted
+    #[arg(long, overrides_with = "append_log")]
+    truncate_log: bool,
+    /// Keep the stdout and stderr logs across restarts, marking where each run starts (the default)
+    #[arg(long, overrides_with = "truncate_log")]
+    append_log: bool,
+    /// Keep up to this much unread stdout and stderr in memory, dropping the oldest beyond it
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "1M")]
+    max_output_buffer: u64,
+    /// Whether the command's terminal passes input on by the line or byte by byte
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = StdinMode::Line)]
+    stdin_mode: StdinMode,
+    /// Run the command on plain pipes instead of a PTY, so input and output pass
+    /// through byte for byte, without echo or \r\n line ends
+    #[arg(long, conflicts_with_all = ["rows", "cols", "stdin_mode"])]
+    no_pty: bool,
+    /// Octal permissions for the daemon's FIFOs, further restricted by the umask
+    #[arg(long, value_name = "MODE", value_parser = parse_mode, default_value = "600")]
+    mode: u32,
+    /// Run the command as this user (name or UID), in that user's primary group
+    #[arg(long)]
+    user: Option<String>,
+    /// Run the command with this group (name or GID)
+    #[arg(long)]
+    group: Option<String>,
+    /// Start the command again when it exits, with a growing delay between attempts
+    #[arg(long, value_enum, default_value_t = RestartPolicy::No)]
+    restart: RestartPolicy,
+    /// Seconds to wait before the first restart; doubles with each attempt up to 30s
+    #[arg(long, value_name = "SECS", default_value_t = default_restart_delay())]
+    restart_delay: u64,
+    /// Give up and mark the daemon failed after this many restarts
+    #[arg(long, value_name = "N", default_value_t = default_restart_max())]
+    restart_max: u32,
+    /// Shell command to run once the command exits for good; it gets ATTYVO_EXIT_CODE
+    #[arg(long, value_name = "CMD")]
+    on_exit: Option<String>,
+    /// Shell command that checks the daemon is healthy by exiting with status 0
+    #[arg(long, value_name = "CMD")]
+    health_cmd: Option<String>,
+    /// Seconds between health checks
+    #[arg(long, value_name = "SECS", default_value_t = default_health_interval(), requires = "health_cmd")]
+    health_interval: u64,
+    /// Restart the command after this many failed checks in a row (needs --restart)
+    #[arg(long, value_name = "N", default_value_t = default_health_retries(), requires = "health_cmd")]
+    health_retries: u32,
+    /// Stop the command for good once it has run this long, e.g. 90s, 30m or 2h
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<u64>,
+    /// Shut the daemon down and remove its files once no input has arrived for this
+    /// long, e.g. 10m
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    idle_timeout: Option<u64>,
+    /// Cap the command's virtual memory (e.g. 512M, 2G); allocations beyond it fail
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    limit_as: Option<u64>,
+    /// Cap the command's CPU time (e.g. 90s, 1h); it is killed once it uses more
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    limit_cpu: Option<u64>,
+    /// Cap how many files the command may have open at once
+    #[arg(long, value_name = "N")]
+    limit_nofile: Option<u64>,
+    /// Run COMMAND and ARGS, joined by spaces, as a command line of $SHELL (or sh),
+    /// so pipes, redirects and globs work
+    #[arg(long)]
+    shell: bool,
+}
+
+/// Formats a size given in KiB with a binary suffix, e.g. `12.5M`.
+fn format_kib(kib: u64) -> String {
+    match kib {
+        kib if kib >= 1024 * 1024 => format!("{:.1}G", kib as f64 / (1024.0 * 1024.0)),
+        kib if kib >= 1024 => format!("{:.1}M", kib as f64 / 1024.0),
+        kib => format!("{}K", kib),
+    }
+}
+
+/// Shows CPU and memory use of every running daemon, refreshing every `interval`
+/// until interrupted, or after the first sample if `once` is set.
+fn show_top(dir: &Path, interval: Duration, once: bool, json: bool) -> Result<()> {
+    if !procfs::available() {
+        return Err(anyhow::anyhow!(
+            "top needs /proc, which is not available on this system"
+        ));
+    }
+    let ticks_per_second = procfs::ticks_per_second() as f64;
+    let sample_all = || -> Result<BTreeMap<String, (DaemonStatus, procfs::Usage)>> {
+        Ok(list_daemon_statuses(dir)?
+            .into_iter()
+            .filter(|status| status.running)
+            .filter_map(|status| {
+                let usage = procfs::sample(status.pid?)?;
+                Some((status.name.clone(), (status, usage)))
+            })
+            .collect())
+    };
+    let mut previous = sample_all()?;
+    let mut previous_at = Instant::now();
+    loop {
+        std::thread::sleep(interval);
+        let current = sample_all()?;
+        let elapsed = previous_at.elapsed().as_secs_f64();
+        previous_at = Instant::now();
+        let mut rows: Vec<(f64, &DaemonStatus, procfs::Usage)> = current
+            .values()
+            .map(|(status, usage)| {
+                let before = previous
+                    .get(&status.name)
+                    .filter(|(old, _)| old.pid == status.pid)
+                    .map_or(usage.cpu_ticks, |(_, old)| old.cpu_ticks);
+                let cpu = usage.cpu_ticks.saturating_sub(before) as f64 / ticks_per_second / elapsed;
+                (cpu * 100.0, status, *usage)
+            })
+            .collect();
+        rows.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+
+        if json {
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(cpu, status, usage)| {
+                    serde_json::json!({
+                        "name": status.name,
+                        "pid": status.pid,
+                        "cpu_percent": cpu,
+                        "rss_kib": usage.rss_kib,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string(&rows)?);
+        } else {
+            if !once && std::io::stdout().is_terminal() {
+                print!("\x1b[H\x1b[2J");
+            }
+            let table: Vec<Vec<String>> = rows
+                .iter()
+                .map(|(cpu, status, usage)| {
+                    vec![
+                        status.name.clone(),
+                        format_pid(status.pid),
+                        format!("{:.1}", cpu),
+                        format_kib(usage.rss_kib),
+                        status.meta.as_ref().map(DaemonMeta::command_line).unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            print_table(&["NAME", "PID", "CPU%", "RSS", "COMMAND"], &table);
+        }
+        std::io::stdout().flush()?;
+        if once {
+            return Ok(());
+        }
+        previous = current;
+    }
+}
+
+/// Prints `rows` as left-aligned columns under `headers`. Cells may be colored;
+/// only the text that shows counts towards the width of a column.
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let visible_width = |cell: &str| {
+        let text = ansi::AnsiStripper::default().strip(cell.as_bytes());
+        String::from_utf8_lossy(&text).chars().count()
+    };
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(visible_width(cell));
+        }
+    }
+    let format_row = |cells: &[&str]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - visible_width(cell))))
+            .collect();
+        line.join("  ").trim_end().to_string()
+    };
+    println!("{}", format_row(headers));
+    for row in rows {
+        let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+        println!("{}", format_row(&cells));
+    }
+}
+
+/// Whether to color what we print: only on a terminal, and never when the
+/// `NO_COLOR` environment variable is set to anything.
+fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::io::stdout().is_terminal()
+}
+
+/// A daemon's PID for display, or a dash once its PID file is gone.
+fn format_pid(pid: Option<i32>) -> String {
+    pid.map_or_else(|| "-".to_string(), |pid| pid.to_string())
+}
+
+/// The daemon's state, in green while it runs, yellow while it is paused or
+/// restarting and red once its command is gone for good, if `color` is set.
+fn colored_state(status: &DaemonStatus, color: bool) -> String {
+    let state = status.state();
+    if !color {
+        return state;
+    }
+    let code = match state.as_str() {
+        "running" => "32",
+        "paused" | "restarting" => "33",
+        _ => "31",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, state)
+}
+
+/// Where `status` goes in `list --sort status`: running daemons first, then
+/// those that may carry on, then those that stopped, with unexplained deaths last.
+fn state_rank(status: &DaemonStatus) -> u8 {
+    match status.state().as_str() {
+        "running" => 0,
+        "paused" => 1,
+        "restarting" => 2,
+        "killed: timeout" => 4,
+        "failed" => 5,
+        "dead" => 6,
+        // Exited with a code or killed by a signal.
+        _ => 3,
+    }
+}
+
+/// Looks up a user by name or numeric UID, returning its UID and, when the user
+/// has a passwd entry, its primary GID.
+fn resolve_user(user: &str) -> Result<(u32, Option<u32>)> {
+    let passwd = match user.parse::<u32>() {
+        Ok(uid) => {
+            let passwd = unsafe { libc::getpwuid(uid) };
+            if passwd.is_null() {
+                return Ok((uid, None));
+            }
+            passwd
+        }
+        Err(_) => {
+            let name = std::ffi::CString::new(user)?;
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        }
+    };
+    if passwd.is_null() {
+        return Err(anyhow::anyhow!("Unknown user: {}", user));
+    }
+    let passwd = unsafe { &*passwd };
+    Ok((passwd.pw_uid, Some(passwd.pw_gid)))
+}
+
+/// Looks up a group by name or numeric GID.
+fn resolve_group(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(group)?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(anyhow::anyhow!("Unknown group: {}", group));
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+/// Parses a byte size with an optional binary `K`, `M` or `G` suffix.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.to_ascii_uppercase().chars().last() {
+        Some('K') => (&s[..s.len() - 1], 1024),
+        Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    let n = digits.parse::<u64>().map_err(|_| format!("invalid size '{}'", s))?;
+    n.checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+/// Parses a duration in seconds with an optional `s`, `m`, `h` or `d` suffix.
+fn parse_duration(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 60 * 60),
+        Some('d') => (&s[..s.len() - 1], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    let n = digits.parse::<u64>().map_err(|_| format!("invalid duration '{}'", s))?;
+    n.checked_mul(multiplier)
+        .ok_or_else(|| format!("duration '{}' is too long", s))
+}
+
+/// Parses a sampling interval in possibly fractional seconds. Shorter intervals
+/// than a tenth of a second are raised to that.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let secs = s.parse::<f64>().map_err(|_| format!("invalid interval '{}'", s))?;
+    if !secs.is_finite() || secs < 0.0 {
+        return Err(format!("invalid interval '{}'", s));
+    }
+    Duration::try_from_secs_f64(secs.max(0.1)).map_err(|_| format!("interval '{}' is too long", s))
+}
+
+/// A name prefix derived from `command`'s file name, with anything a daemon
+/// name cannot contain replaced.
+fn name_prefix_for(command: &str) -> String {
+    let base = Path::new(command)
+        .file_name()
+        .map_or_else(|| command.into(), |base| base.to_string_lossy());
+    let prefix: String = base
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '-') { c } else { '-' })
+        .take(80)
+        .collect();
+    match prefix.trim_matches('-') {
+        "" => "daemon".to_string(),
+        prefix => prefix.to_string(),
+    }
+}
+
+/// The first of `<prefix>-1`, `<prefix>-2`, ... that no daemon in `dir` uses.
+fn pick_free_name(dir: &Path, prefix: &str) -> Result<String> {
+    ensure_pipe_dir_exists(dir)?;
+    let taken = |name: &str| {
+        let paths = DaemonPaths::new(dir, name);
+        [&paths.pid, &paths.meta, &paths.lock].iter().any(|path| path.exists())
+    };
+    let name = (1..)
+        .map(|n| format!("{}-{}", prefix, n))
+        .find(|name| !taken(name))
+        .expect("some suffix is free");
+    validate_name(&name).map_err(anyhow::Error::msg)?;
+    Ok(name)
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s.trim_start_matches("0o"), 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("invalid octal mode '{}'", s)),
+    }
+}
+
+/// The settings for running `command` with `args` as `options` describe them.
+fn daemon_meta(options: DaemonOptions, command: String, args: Vec<String>) -> Result<DaemonMeta> {
+    let DaemonOptions {
+        rows,
+        cols,
+        env,
+        env_file,
+        env_clear,
+        cwd,
+        log_max_size,
+        truncate_log,
+        append_log: _,
+        stdin_mode,
+        no_pty,
+        max_output_buffer,
+        mode,
+        user,
+        group,
+        restart,
+        restart_delay,
+        restart_max,
+        on_exit,
+        health_cmd,
+        health_interval,
+        health_retries,
+        timeout,
+        idle_timeout,
+        limit_as,
+        limit_cpu,
+        limit_nofile,
+        shell,
+    } = options;
+    let cwd = cwd.map(|cwd| resolve_cwd(&cwd)).transpose()?;
+    let user = user.map(|user| resolve_user(&user)).transpose()?;
+    let gid = group
+        .map(|group| resolve_group(&group))
+        .transpose()?
+        .or(user.and_then(|(_, gid)| gid));
+    let (default_rows, default_cols) =
+        terminal_size().unwrap_or((default_rows(), default_cols()));
+    let env: BTreeMap<String, String> = env.into_iter().collect();
+    let env_file = env_file.map(std::path::absolute).transpose()?;
+    let file_env: BTreeMap<String, String> = match &env_file {
+        Some(path) => envfile::load(path)?.into_iter().collect(),
+        None => BTreeMap::new(),
+    };
+    // Only for working out TERM and SHELL, which are no secrets.
+    let mut merged_env = file_env.clone();
+    merged_env.extend(env.clone());
+    let shell = shell.then(|| {
+        merged_env
+            .get("SHELL")
+            .cloned()
+            .or_else(|| std::env::var("SHELL").ok().filter(|shell| !shell.is_empty()))
+            .unwrap_or_else(|| "/bin/sh".to_string())
+    });
+    Ok(DaemonMeta {
+        rows: rows.unwrap_or(default_rows),
+        cols: cols.unwrap_or(default_cols),
+        term: (!no_pty).then(|| effective_term(&merged_env)),
+        env,
+        env_clear,
+        env_file,
+        env_file_keys: file_env.into_keys().collect(),
+        cwd,
+        log_max_size,
+        log_policy: if truncate_log {
+            LogPolicy::Truncate
+        } else {
+            LogPolicy::Append
+        },
+        stdin_mode,
+        no_pty,
+        max_output_buffer,
+        fifo_mode: mode,
+        uid: user.map(|(uid, _)| uid),
+        gid,
+        restart,
+        restart_delay,
+        restart_max,
+        on_exit,
+        health_cmd,
+        health_interval,
+        health_retries,
+        timeout,
+        idle_timeout,
+        limit_as,
+        limit_cpu,
+        limit_nofile,
+        shell,
+        ..DaemonMeta::new(command, args)
+    })
+}
+
+/// Shows the size of `meta`'s PTY, or that it has none.
+fn print_size(meta: &DaemonMeta) {
+    if meta.no_pty {
+        println!("Size:    no PTY, plain pipes");
+    } else {
+        println!("Size:    {}x{}", meta.rows, meta.cols);
+    }
+}
+
+/// Whether daemon `name` runs on a PTY, whose output may need its escape
+/// sequences stripped. A `--no-pty` daemon's output is passed on as it is.
+fn on_pty(dir: &Path, name: &str) -> bool {
+    !daemon_status(dir, name).is_ok_and(|status| status.meta.is_some_and(|meta| meta.no_pty))
+}
+
+/// Lists the variables `meta`'s env file defines, without their values.
+fn print_env_file_keys(meta: &DaemonMeta) {
+    let Some(env_file) = &meta.env_file else {
+        return;
+    };
+    for key in meta.env_file_keys.iter().filter(|key| !meta.env.contains_key(*key)) {
+        println!("Env:     {} (from {})", key, env_file.display());
+    }
+}
+
+fn parse_env_pair(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
+
+fn parse_arg_override(s: &str) -> Result<(usize, String), String> {
+    match s.split_once('=') {
+        Some((position, value)) => match position.parse::<usize>() {
+            Ok(position) if position > 0 => Ok((position, value.to_string())),
+            _ => Err(format!("argument position must be a number from 1, got '{}'", position)),
+        },
+        None => Err(format!("expected N=VALUE, got '{}'", s)),
+    }
+}
+
+/// Warns about the files `list_daemons` could not consider.
+fn print_skipped(skipped: &[PathBuf]) {
+    for path in skipped {
+        eprintln!("Skipping non-UTF-8 file name {:?}", path);
+    }
+}
+
+/// Warns about the files passed over and the daemons `action` failed for.
+fn print_failures(outcome: &BatchOutcome, action: &str) {
+    print_skipped(&outcome.skipped);
+    for (daemon, e) in &outcome.failed {
+        eprintln!("Failed to {} daemon '{}': {}", action, daemon, e);
+    }
+}
+
+/// Names of the existing daemons, offered when completing a name argument.
+/// Completion happens before arguments are parsed, so `--dir` is not honoured.
+/// Nothing may be created or printed here, so a missing directory offers nothing.
+fn daemon_name_candidates() -> Vec<CompletionCandidate> {
+    let dir = std::env::var_os("ATTYVO_DIR").map_or_else(|| PathBuf::from(DEFAULT_PIPE_DIR), PathBuf::from);
+    find_daemons(&dir)
+        .map(|daemons| daemons.names)
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Runs the command line given to this process and exits with its status.
+pub fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(error::exit_code(&e));
+    }
+}
+
+fn run() -> Result<()> {
+    CompleteEnv::with_factory(Cli::command).complete();
+    let cli = Cli::parse();
+    logging::init(cli.verbose);
+    let dir = std::path::absolute(&cli.dir)?;
+    let quiet = cli.quiet;
+
+    match cli.command {
+        Commands::Create {
+            options,
+            wait_ready,
+            dry_run,
+            ready_timeout,
+            auto_name,
+            allow_duplicate,
+            name_prefix,
+            name,
+            command,
+            args,
+        } => {
+            let auto_name = auto_name || name_prefix.is_some();
+            // Without a name, what was parsed as the name is really the command.
+            let (name, command, args) = match command {
+                Some(command) if !auto_name => (name, command, args),
+                command => {
+                    let args: Vec<String> = command.into_iter().chain(args).collect();
+                    let prefix = name_prefix.unwrap_or_else(|| name_prefix_for(&name));
+                    (pick_free_name(&dir, &prefix)?, name, args)
+                }
+            };
+            validate_name(&name).map_err(anyhow::Error::msg)?;
+            let meta = daemon_meta(options, command, args)?;
+            if !allow_duplicate {
+                for other in find_duplicates(&dir, &meta)? {
+                    eprintln!(
+                        "Warning: daemon '{}' already runs `{}` in the same directory; \
+                         pass --allow-duplicate if that is intended",
+                        other,
+                        meta.command_line()
+                    );
+                }
+            }
+            if dry_run {
+                return print_create_plan(&dir, &name, &meta, cli.json);
+            }
+            let ready_timeout = wait_ready.then(|| Duration::from_secs(ready_timeout));
+            // Just the name, so scripts can capture it. Without --wait-ready this
+            // process turns into the daemon, so it has to be said up front.
+            let print_name = || {
+                if cli.json {
+                    println!("{}", serde_json::json!({ "name": name }));
+                } else {
+                    println!("{}", name);
+                }
+            };
+            if auto_name && ready_timeout.is_none() {
+                print_name();
+            }
+            create(&dir, &name, &meta, ready_timeout)?;
+            if !auto_name {
+                say!(quiet, "Daemon '{}' created and started", name);
+            } else if ready_timeout.is_some() {
+                print_name();
+            }
+        }
+        Commands::Run {
+            options,
+            name,
+            command,
+            args,
+        } => {
+            let meta = daemon_meta(options, command, args)?;
+            std::process::exit(run_foreground(&dir, &name, &meta)?);
+        }
+        Commands::Exec { command, args } => {
+            let (rows, cols) = terminal_size().unwrap_or((default_rows(), default_cols()));
+            // The output goes straight to our terminal, so describe that one.
+            let term = std::env::var("TERM").unwrap_or_else(|_| DEFAULT_TERM.to_string());
+            let meta = DaemonMeta {
+                rows,
+                cols,
+                term: Some(term),
+                ..DaemonMeta::new(command, args)
+            };
+            std::process::exit(exec::exec(&dir, &meta)?);
+        }
+        Commands::Write {
+            name,
+            messages,
+            no_newline,
+            raw,
+            base64,
+        } => {
+            let count = messages.len();
+            let mut lines = Vec::new();
+            for message in messages {
+                if base64 {
+                    lines.extend(decode_base64(&message)?);
+                    continue;
+                }
+                if !lines.is_empty() {
+                    lines.push(b'\n');
+                }
+                if raw {
+                    lines.extend(unescape(&message)?);
+                } else {
+                    lines.extend(message.into_bytes());
+                }
+            }
+            // All lines go through a single open of the pipe.
+            write(&dir, &name, &lines, !no_newline && !base64)?;
+            if count == 1 {
+                say!(quiet, "Message written to daemon '{}'", name);
+            } else {
+                say!(quiet, "{} messages written to daemon '{}'", count, name);
+            }
+        }
+        Commands::Send {
+            name,
+            message,
+            no_newline,
+            raw,
+            idle,
+            until,
+            timeout,
+        } => {
+            let message = if raw {
+                unescape(&message)?
+            } else {
+                message.into_bytes()
+            };
+            let end = send::ResponseEnd {
+                idle: match idle {
+                    Some(idle) => Some(Duration::from_millis(idle)),
+                    None if until.is_none() => Some(send::DEFAULT_IDLE),
+                    None => None,
+                },
+                until,
+                timeout: timeout.map(Duration::from_secs),
+            };
+            let (response, timed_out) = send::send(&dir, &name, &message, !no_newline, &end)?;
+            let response = if std::io::stdout().is_terminal() || !on_pty(&dir, &name) {
+                response
+            } else {
+                ansi::AnsiStripper::default().strip(&response)
+            };
+            let mut stdout = std::io::stdout();
+            if cli.json {
+                let output = String::from_utf8_lossy(&response);
+                let value = serde_json::json!({ "name": name, "output": output, "timed_out": timed_out });
+                writeln!(stdout, "{}", value)?;
+            } else {
+                stdout.write_all(&response)?;
+            }
+            stdout.flush()?;
+            if timed_out {
+                eprintln!("Timed out waiting for the response from daemon '{}'", name);
+                std::process::exit(WAIT_TIMEOUT_EXIT_CODE);
+            }
+        }
+        Commands::WriteFile { name, path, base64 } => {
+            let written = write_file(&dir, &name, &path, base64)?;
+            say!(quiet, "{} byte(s) written to daemon '{}'", written, name);
+        }
+        Commands::Eof { name } => {
+            send_eof(&dir, &name)?;
+            say!(quiet, "EOF sent to daemon '{}'", name);
+        }
+        Commands::Interrupt { name } => {
+            send_interrupt(&dir, &name)?;
+            say!(quiet, "Interrupt sent to daemon '{}'", name);
+        }
+        Commands::Key { name, keys } => {
+            write(&dir, &name, &keys.concat(), false)?;
+            say!(quiet, "{} key(s) sent to daemon '{}'", keys.len(), name);
+        }
+        Commands::Attach { name, detach_keys } => {
+            if !quiet {
+                eprint!(
+                    "Attached to daemon '{}'; press {} to detach\r\n",
+                    name, detach_keys.spec
+                );
+            }
+            match attach::attach(&dir, &name, &detach_keys)? {
+                attach::AttachEnd::Detached if !quiet => {
+                    eprintln!("\r\nDetached from daemon '{}'", name)
+                }
+                attach::AttachEnd::DaemonExited if !quiet => {
+                    eprintln!("\r\nDaemon '{}' exited", name)
+                }
+                _ => {}
+            }
+        }
+        Commands::ReadStderr { name, follow } => {
+            let print_output = |output: &[u8]| -> Result<()> {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(output)?;
+                stdout.flush()?;
+                Ok(())
+            };
+            if follow {
+                follow_stderr(&dir, &name, print_output)?;
+            } else {
+                print_output(&read_stderr(&dir, &name)?)?;
+            }
+        }
+        Commands::Read {
+            name,
+            follow,
+            from_start,
+            strip_ansi,
+            no_strip_ansi,
+            lossy,
+            max_bytes,
+            wait,
+            timeout,
+            lines,
+            require_output,
+            combined,
+            consumer,
+            clear,
+        } => {
+            if clear {
+                let mut discarded = read_stdout(&dir, &name, &consumer, None)?.0.len();
+                if combined {
+                    discarded += read_stderr(&dir, &name)?.len();
+                }
+                if cli.json {
+                    println!("{}", serde_json::json!({ "name": name, "discarded": discarded }));
+                } else {
+                    say!(quiet, "Discarded {} byte(s) of output from daemon '{}'", discarded, name);
+                }
+                return Ok(());
+            }
+            let strip_ansi = strip_ansi
+                || (!no_strip_ansi && !std::io::stdout().is_terminal() && on_pty(&dir, &name));
+            let mut stripper = ansi::AnsiStripper::default();
+            let mut received = 0;
+            let mut print_output = |output: &[u8]| -> Result<()> {
+                received += output.len();
+                let stripped;
+                let output = if strip_ansi {
+                    stripped = stripper.strip(output);
+                    &stripped[..]
+                } else {
+                    output
+                };
+                let mut stdout = std::io::stdout();
+                if cli.json {
+                    let output = String::from_utf8_lossy(output);
+                    let value = serde_json::json!({ "name": name, "output": output });
+                    writeln!(stdout, "{}", value)?;
+                } else if lossy {
+                    stdout.write_all(String::from_utf8_lossy(output).as_bytes())?;
+                } else {
+                    stdout.write_all(output)?;
+                }
+                stdout.flush()?;
+                Ok(())
+            };
+            if from_start {
+                print_output(&read_stdout_log(&dir, &name)?)?;
+            } else if let Some(lines) = lines {
+                // The unread output is part of the tail; skip it so that it is
+                // neither printed twice nor returned by the next read.
+                read_stdout(&dir, &name, &consumer, None)?;
+                print_output(last_lines(&read_stdout_log(&dir, &name)?, lines))?;
+                if follow {
+                    read_as_consumer(&dir, &name, &consumer, None, true, print_output)?;
+                }
+            } else if follow {
+                read_as_consumer(&dir, &name, &consumer, None, true, print_output)?;
+            } else if combined {
+                // Stdout and stderr do not say what was written when, so stdout simply goes first.
+                let (mut output, _) = read_stdout(&dir, &name, &consumer, None)?;
+                output.extend(read_stderr(&dir, &name)?);
+                print_output(&output)?;
+            } else {
+                let timeout = timeout.map(Duration::from_secs);
+                if wait && !wait_for_stdout(&dir, &name, &consumer, timeout)? {
+                    eprintln!("Timed out waiting for output from daemon '{}'", name);
+                    std::process::exit(WAIT_TIMEOUT_EXIT_CODE);
+                }
+                let (output, truncated) = read_stdout(&dir, &name, &consumer, max_bytes)?;
+                print_output(&output)?;
+                if truncated {
+                    eprintln!("Output truncated after {} bytes; read again for the rest", output.len());
+                }
+            }
+            if require_output && received == 0 {
+                return Err(Failure::NoOutput(name).into());
+            }
+        }
+        Commands::Peek {
+            name,
+            lines,
+            no_strip_ansi,
+        } => {
+            let log = read_stdout_log(&dir, &name)?;
+            let output = lines.map_or(&log[..], |lines| last_lines(&log, lines));
+            let output = if no_strip_ansi || std::io::stdout().is_terminal() || !on_pty(&dir, &name) {
+                output.to_vec()
+            } else {
+                ansi::AnsiStripper::default().strip(output)
+            };
+            let mut stdout = std::io::stdout();
+            if cli.json {
+                let output = String::from_utf8_lossy(&output);
+                writeln!(stdout, "{}", serde_json::json!({ "name": name, "output": output }))?;
+            } else {
+                stdout.write_all(&output)?;
+            }
+            stdout.flush()?;
+        }
+        Commands::Logs {
+            name,
+            follow,
+            lines,
+            timestamps,
+        } => {
+            let mut stdout = std::io::stdout();
+            read_logs(&dir, &name, lines, follow, |record| {
+                if cli.json {
+                    let value = serde_json::json!({
+                        "time": record.millis,
+                        "stream": record.stream.label(),
+                        "line": String::from_utf8_lossy(&record.line),
+                    });
+                    writeln!(stdout, "{}", value)?;
+                } else {
+                    if timestamps {
+                        write!(stdout, "{} ", format_timestamp(record.millis))?;
+                    }
+                    write!(stdout, "[{}] ", record.stream.label())?;
+                    stdout.write_all(&record.line)?;
+                    writeln!(stdout)?;
+                }
+                stdout.flush()?;
+                Ok(())
+            })?;
+        }
+        Commands::Doctor => {
+            let checks = doctor::run(&dir);
+            let failed = checks.iter().filter(|check| check.status == doctor::Status::Fail).count();
+            if cli.json {
+                let checks: Vec<_> = checks
+                    .iter()
+                    .map(|check| {
+                        let status = match check.status {
+                            doctor::Status::Pass => "pass",
+                            doctor::Status::Warn => "warn",
+                            doctor::Status::Fail => "fail",
+                        };
+                        serde_json::json!({
+                            "check": check.label,
+                            "status": status,
+                            "detail": check.detail,
+                            "hint": check.hint,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&checks)?);
+            } else {
+                for check in &checks {
+                    let status = match check.status {
+                        doctor::Status::Pass => "ok",
+                        doctor::Status::Warn => "warn",
+                        doctor::Status::Fail => "FAIL",
+                    };
+                    println!("[{:<4}] {}: {}", status, check.label, check.detail);
+                    if let Some(hint) = &check.hint {
+                        println!("       {}", hint);
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(anyhow::anyhow!("{} check(s) failed", failed));
+            }
+        }
+        Commands::Rename { old, new } => {
+            rename_daemon(&dir, &old, &new)?;
+            say!(quiet, "Daemon '{}' renamed to '{}'", old, new);
+        }
+        Commands::Clone {
+            src,
+            dst,
+            arg_overrides,
+            env,
+        } => {
+            clone_daemon(&dir, &src, &dst, &arg_overrides, &env)?;
+            say!(quiet, "Daemon '{}' created from '{}' and started", dst, src);
+        }
+        Commands::Pipe { list: true, .. } => {
+            let relays = pipe::list(&dir)?;
+            if cli.json {
+                let relays: Vec<_> = relays
+                    .iter()
+                    .map(|relay| {
+                        serde_json::json!({ "src": relay.src, "dst": relay.dst, "pid": relay.pid })
+                    })
+                    .collect();
+                println!("{}", serde_json::Value::Array(relays));
+            } else if relays.is_empty() {
+                say!(quiet, "No daemons are piped into one another");
+            } else {
+                for relay in relays {
+                    println!("{} -> {} (relay PID {})", relay.src, relay.dst, relay.pid);
+                }
+            }
+        }
+        Commands::Pipe { stop, src, dst, .. } => {
+            // clap requires both names unless --list is given.
+            let (src, dst) = (src.unwrap_or_default(), dst.unwrap_or_default());
+            if stop {
+                pipe::stop(&dir, &src, &dst)?;
+                say!(quiet, "Stopped piping daemon '{}' into daemon '{}'", src, dst);
+            } else {
+                let relay = pipe::start(&dir, &src, &dst)?;
+                say!(
+                    quiet,
+                    "Piping output of daemon '{}' into daemon '{}' (relay PID {})",
+                    src,
+                    dst,
+                    relay.pid
+                );
+            }
+        }
+        Commands::Resize { name, rows, cols } => {
+            resize_daemon(&dir, &name, rows, cols)?;
+            say!(quiet, "Daemon '{}' resized to {}x{}", name, rows, cols);
+        }
+        Commands::Paths { name } => {
+            let paths = DaemonPaths::new(&dir, &name);
+            if cli.json {
+                let json: serde_json::Map<_, _> = paths
+                    .labelled()
+                    .into_iter()
+                    .map(|(label, path)| (label.to_string(), path.display().to_string().into()))
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&json)?);
+            } else {
+                let rows: Vec<Vec<String>> = paths
+                    .labelled()
+                    .into_iter()
+                    .map(|(label, path)| vec![label.to_string(), path.display().to_string()])
+                    .collect();
+                print_table(&["FILE", "PATH"], &rows);
+            }
+        }
+        Commands::Status { name } => {
+            let status = daemon_status(&dir, &name)?;
+            // Why a command died is usually in the last thing it wrote to stderr.
+            let stderr = (!status.running)
+                .then(|| stderr_tail(&dir, &name, STATUS_STDERR_LINES))
+                .flatten();
+            if cli.json {
+                let mut json = status.to_json();
+                json["stderr_tail"] = stderr.into();
+                println!("{}", serde_json::to_string_pretty(&json)?);
+                return Ok(());
+            }
+            println!("Name:    {}", name);
+            println!("PID:     {}", format_pid(status.pid));
+            println!("State:   {}", colored_state(&status, use_color()));
+            if let Some(meta) = &status.meta {
+                println!("Command: {}", meta.command_line());
+                print_size(meta);
+                if let Some(term) = &meta.term {
+                    println!("Term:    {}", term);
+                }
+                if let Some(cwd) = &meta.cwd {
+                    println!("Cwd:     {}", cwd.display());
+                }
+                for (key, value) in &meta.env {
+                    println!("Env:     {}={}", key, value);
+                }
+                print_env_file_keys(meta);
+                if meta.restart != RestartPolicy::No {
+                    match &meta.last_exit {
+                        Some(last_exit) => println!(
+                            "Restart: {}/{} (last run {})",
+                            meta.restarts, meta.restart_max, last_exit
+                        ),
+                        None => println!("Restart: {}/{}", meta.restarts, meta.restart_max),
+                    }
+                }
+            }
+            if let Some(uptime) = status.uptime() {
+                println!("Uptime:  up {}", format_duration(uptime));
+            }
+            if let Some(health) = status.health.as_ref().filter(|_| status.running) {
+                let checked = format_duration(unix_now().saturating_sub(health.checked_at));
+                match health.failures {
+                    0 => println!("Health:  healthy (checked {} ago)", checked),
+                    failures => println!(
+                        "Health:  unhealthy ({} failed checks in a row, last {} ago)",
+                        failures, checked
+                    ),
+                }
+            }
+            if let Some(stderr) = stderr {
+                println!("Stderr:");
+                for line in stderr.lines() {
+                    println!("  {}", line);
+                }
+            }
+        }
+        Commands::Wait { name, timeout } => {
+            match wait_daemon(&dir, &name, timeout.map(Duration::from_secs))? {
+                Some(code) => std::process::exit(code),
+                None => {
+                    eprintln!("Timed out waiting for daemon '{}'", name);
+                    std::process::exit(WAIT_TIMEOUT_EXIT_CODE);
+                }
+            }
+        }
+        Commands::WaitOutput {
+            name,
+            pattern,
+            timeout,
+        } => match wait_for_output(&dir, &name, &pattern, timeout.map(Duration::from_secs))? {
+            Some(line) if cli.json => {
+                println!("{}", serde_json::json!({ "name": name, "line": line }));
+            }
+            Some(line) => say!(quiet, "{}", line),
+            None => {
+                eprintln!("Timed out waiting for output from daemon '{}'", name);
+                std::process::exit(WAIT_TIMEOUT_EXIT_CODE);
+            }
+        },
+        Commands::Signal { name, signal } => {
+            signal_daemon(&dir, &name, signal)?;
+            say!(quiet, "Signal {} sent to daemon '{}'", signal, name);
+        }
+        Commands::Pause { name } => {
+            pause_daemon(&dir, &name)?;
+            say!(quiet, "Daemon '{}' paused", name);
+        }
+        Commands::Resume { name } => {
+            resume_daemon(&dir, &name)?;
+            say!(quiet, "Daemon '{}' resumed", name);
+        }
+        Commands::Restart { name } => {
+            restart_daemon(&dir, &name, None)?;
+            say!(quiet, "Daemon '{}' restarted", name);
+        }
+        Commands::Watch {
+            name,
+            paths,
+            extensions,
+            debounce,
+        } => {
+            let filter = watch::Filter {
+                extensions: extensions
+                    .into_iter()
+                    .map(|ext| ext.trim_start_matches('.').to_string())
+                    .collect(),
+                ignore: dir.clone(),
+            };
+            ensure_pid_file(&dir, &name)?;
+            say!(quiet, "Watching for changes to restart daemon '{}'", name);
+            watch::watch(&dir, &name, &paths, &filter, Duration::from_millis(debounce), |changed, result| {
+                let changed: Vec<String> =
+                    changed.iter().map(|path| path.display().to_string()).collect();
+                match result {
+                    Ok(()) if cli.json => {
+                        println!("{}", serde_json::json!({ "restarted": name, "changed": changed }))
+                    }
+                    Ok(()) => {
+                        say!(quiet, "Daemon '{}' restarted ({} changed)", name, changed.join(", "))
+                    }
+                    Err(e) => eprintln!("Failed to restart daemon '{}': {:#}", name, e),
+                }
+                Ok(())
+            })?;
+        }
+        Commands::Kill {
+            name,
+            timeout,
+            force,
+            keep_logs,
+        } => {
+            match kill_daemon(&dir, &name, Duration::from_secs(timeout), force, keep_logs)? {
+                KillOutcome::AlreadyStopped => {
+                    say!(quiet, "Daemon '{}' was not running; cleaned up", name)
+                }
+                KillOutcome::Graceful => say!(quiet, "Daemon '{}' killed", name),
+                KillOutcome::Forced => say!(quiet, 
+                    "Daemon '{}' ignored SIGTERM for {}s and was killed with SIGKILL",
+                    name, timeout
+                ),
+            }
+            if keep_logs {
+                say!(quiet, "Kept the logs of daemon '{}'; `attyvo clean {}` removes them", name, name);
+            }
+        }
+        Commands::List {
+            running,
+            sort,
+            filter,
+        } => {
+            let listed = list_daemons(&dir)?;
+            print_skipped(&listed.skipped);
+            let mut daemons = daemon_statuses(&dir, &listed.names);
+            daemons.retain(|daemon| {
+                (!running || daemon.running)
+                    && filter.as_ref().is_none_or(|pattern| glob_match(pattern, &daemon.name))
+            });
+            // The daemons come sorted by name, which breaks ties.
+            match sort {
+                ListOrder::Name => {}
+                ListOrder::Pid => daemons.sort_by_key(|daemon| daemon.pid),
+                ListOrder::Uptime => daemons.sort_by_key(|daemon| std::cmp::Reverse(daemon.uptime())),
+                ListOrder::Status => daemons.sort_by_key(state_rank),
+            }
+            if cli.json {
+                let daemons: Vec<_> = daemons.iter().map(DaemonStatus::to_json).collect();
+                println!("{}", serde_json::to_string_pretty(&daemons)?);
+            } else if daemons.is_empty() {
+                println!("No daemons");
+            } else {
+                let color = use_color();
+                let rows: Vec<Vec<String>> = daemons
+                    .iter()
+                    .map(|daemon| {
+                        vec![
+                            daemon.name.clone(),
+                            format_pid(daemon.pid),
+                            colored_state(daemon, color),
+                            daemon.uptime().map(format_duration).unwrap_or_default(),
+                            daemon
+                                .meta
+                                .as_ref()
+                                .map(DaemonMeta::command_line)
+                                .unwrap_or_default(),
+                        ]
+                    })
+                    .collect();
+                print_table(&["NAME", "PID", "STATE", "UPTIME", "COMMAND"], &rows);
+            }
+        }
+        Commands::Top { interval, once } => {
+            show_top(&dir, interval, once, cli.json)?;
+        }
+        Commands::Up { file } => compose_up(&dir, &file, quiet)?,
+        Commands::Down { file, timeout } => {
+            compose_down(&dir, &file, Duration::from_secs(timeout), quiet)?;
+        }
+        Commands::Prune => {
+            let pruned = prune_daemons(&dir)?;
+            if pruned.is_empty() {
+                say!(quiet, "No dead daemons to prune");
+            } else {
+                say!(quiet, "Pruned {} daemon(s):", pruned.len());
+                for daemon in pruned {
+                    say!(quiet, "  - {}", daemon);
+                }
+            }
+        }
+        Commands::Clean { name } => {
+            let cleaned = clean_daemons(&dir, name.as_deref())?;
+            if cli.json {
+                let cleaned: Vec<_> = cleaned
+                    .iter()
+                    .map(|daemon| {
+                        serde_json::json!({
+                            "name": daemon.name,
+                            "files": daemon.files,
+                            "bytes": daemon.bytes,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::Value::Array(cleaned));
+            } else if cleaned.is_empty() {
+                say!(quiet, "No leftover files to clean");
+            } else {
+                for daemon in &cleaned {
+                    say!(
+                        quiet,
+                        "Removed {} file(s) of daemon '{}' ({})",
+                        daemon.files,
+                        daemon.name,
+                        format_kib(daemon.bytes.div_ceil(1024))
+                    );
+                }
+                let freed = cleaned.iter().map(|daemon| daemon.bytes).sum::<u64>();
+                say!(quiet, "Freed {}", format_kib(freed.div_ceil(1024)));
+            }
+        }
+        Commands::Spawn { name, restart } => {
+            if restart {
+                restart_daemon(&dir, &name, Some(DEFAULT_READY_TIMEOUT))?;
+            } else {
+                let meta: DaemonMeta = serde_json::from_reader(std::io::stdin())?;
+                create(&dir, &name, &meta, Some(DEFAULT_READY_TIMEOUT))?;
+            }
+        }
+        Commands::Events => {
+            ensure_pipe_dir_exists(&dir)?;
+            let mut stdout = std::io::stdout();
+            events::listen(&dir, |event| {
+                writeln!(stdout, "{}", event)?;
+                stdout.flush()?;
+                Ok(())
+            })?;
+        }
+        Commands::Completions { shell } => {
+            let shells = Shells::builtins();
+            let shell = shells
+                .completer(&shell)
+                .ok_or_else(|| anyhow::anyhow!("Unsupported shell: {}", shell))?;
+            let completer = std::env::current_exe()?;
+            shell.write_registration(
+                "COMPLETE",
+                "attyvo",
+                "attyvo",
+                &completer.to_string_lossy(),
+                &mut std::io::stdout(),
+            )?;
+        }
+        Commands::KillAll {
+            filter,
+            signal: Some(signal),
+            ..
+        } => {
+            let outcome = signal_all_daemons(&dir, signal, filter.as_deref())?;
+            print_failures(&outcome, "signal");
+            let signalled = outcome.done;
+            if cli.json {
+                println!("{}", serde_json::json!({ "signalled": signalled }));
+            } else if signalled.is_empty() {
+                say!(quiet, "No daemons to signal");
+            } else {
+                say!(quiet, "Signal {} sent to {} daemon(s):", signal, signalled.len());
+                for daemon in signalled {
+                    say!(quiet, "  - {}", daemon);
+                }
+            }
+        }
+        Commands::KillAll {
+            filter,
+            timeout,
+            signal: None,
+        } => {
+            let outcome = kill_all_daemons(&dir, Duration::from_secs(timeout), filter.as_deref())?;
+            print_failures(&outcome, "kill");
+            let killed = outcome.done;
+            if cli.json {
+                println!("{}", serde_json::json!({ "killed": killed }));
+            } else if killed.is_empty() {
+                say!(quiet, "No daemons to kill");
+            } else {
+                say!(quiet, "Killed {} daemon(s):", killed.len());
+                for daemon in killed {
+                    say!(quiet, "  - {}", daemon);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4 * 1024));
+        assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("M"), Err("invalid size 'M'".to_string()));
+        assert_eq!(parse_size("1T"), Err("invalid size '1T'".to_string()));
+        assert_eq!(parse_size("-1K"), Err("invalid size '-1K'".to_string()));
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        let size = format!("{}G", u64::MAX / 1024);
+        assert_eq!(
+            parse_size(&size),
+            Err(format!("size '{}' is too large", size))
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(30 * 60));
+        assert_eq!(parse_duration("2h"), Ok(2 * 60 * 60));
+        assert_eq!(parse_duration("1d"), Ok(24 * 60 * 60));
+        assert_eq!(
+            parse_duration("1H"),
+            Err("invalid duration '1H'".to_string())
+        );
+        assert_eq!(parse_duration(""), Err("invalid duration ''".to_string()));
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        let duration = format!("{}d", u64::MAX / 60);
+        assert_eq!(
+            parse_duration(&duration),
+            Err(format!("duration '{}' is too long", duration))
+        );
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_interval("0.5"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_interval("0"), Ok(Duration::from_millis(100)));
+        for bad in ["inf", "NaN", "-1", "soon"] {
+            assert_eq!(
+                parse_interval(bad),
+                Err(format!("invalid interval '{}'", bad))
+            );
+        }
+        assert_eq!(
+            parse_interval("1e300"),
+            Err("interval '1e300' is too long".to_string())
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Declarative descriptions of a set of daemons, as read by `up` and `down`.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::RestartPolicy;
+
+/// The contents of an `attyvo.toml` file: one `[daemons.<name>]` table per daemon.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub daemons: BTreeMap<String, Service>,
+}
+
+/// How to start one daemon. Anything left out gets the same default as `create`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Service {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub env_clear: bool,
+    /// Working directory, relative to the directory holding the config file,
+    /// which is also the default.
+    pub cwd: Option<PathBuf>,
+    pub rows: Option<u16>,
+    pub cols: Option<u16>,
+    pub restart: Option<RestartPolicy>,
+    pub on_exit: Option<String>,
+    /// Daemons that must be up and running before this one is started.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+impl Config {
+    /// Names of the daemons ordered so that each comes after everything it
+    /// depends on. Unknown dependencies and cycles are errors.
+    pub fn start_order(&self) -> Result<Vec<&str>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Visiting,
+            Done,
+        }
+
+        fn visit<'a>(
+            config: &'a Config,
+            name: &'a str,
+            marks: &mut BTreeMap<&'a str, Mark>,
+            path: &mut Vec<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> Result<()> {
+            match marks.get(name) {
+                Some(Mark::Done) => return Ok(()),
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|&entry| entry == name).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(name);
+                    return Err(anyhow::anyhow!("Dependency cycle: {}", cycle.join(" -> ")));
+                }
+                None => {}
+            }
+            marks.insert(name, Mark::Visiting);
+            path.push(name);
+            for dependency in &config.daemons[name].depends_on {
+                if !config.daemons.contains_key(dependency) {
+                    return Err(anyhow::anyhow!(
+                        "Daemon '{}' depends on unknown daemon '{}'",
+                        name,
+                        dependency
+                    ));
+                }
+                visit(config, dependency, marks, path, order)?;
+            }
+            path.pop();
+            marks.insert(name, Mark::Done);
+            order.push(name);
+            Ok(())
+        }
+
+        let mut marks = BTreeMap::new();
+        let mut order = Vec::new();
+        for name in self.daemons.keys() {
+            visit(self, name, &mut marks, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+}
+
+/// Reads the config at `path`, resolving each daemon's `cwd` against the file's directory.
+pub fn load(path: &Path) -> Result<Config> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+    let mut config: Config = toml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))?;
+    let base = std::path::absolute(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    for service in config.daemons.values_mut() {
+        service.cwd = Some(match service.cwd.take() {
+            Some(cwd) => base.join(cwd),
+            None => base.clone(),
+        });
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn starts_dependencies_first() {
+        let config = config(
+            r#"
+            [daemons.web]
+            command = "server"
+            depends_on = ["db", "cache"]
+
+            [daemons.cache]
+            command = "redis-server"
+            depends_on = ["db"]
+
+            [daemons.db]
+            command = "postgres"
+
+            [daemons.worker]
+            command = "worker"
+            "#,
+        );
+        assert_eq!(
+            config.start_order().unwrap(),
+            ["db", "cache", "web", "worker"]
+        );
+    }
+
+    #[test]
+    fn reports_dependency_cycles() {
+        let config = config(
+            r#"
+            [daemons.a]
+            command = "a"
+            depends_on = ["b"]
+
+            [daemons.b]
+            command = "b"
+            depends_on = ["c"]
+
+            [daemons.c]
+            command = "c"
+            depends_on = ["b"]
+            "#,
+        );
+        assert_eq!(
+            config.start_order().unwrap_err().to_string(),
+            "Dependency cycle: b -> c -> b"
+        );
+    }
+
+    #[test]
+    fn reports_unknown_dependencies() {
+        let config = config(
+            r#"
+            [daemons.web]
+            command = "server"
+            depends_on = ["db"]
+            "#,
+        );
+        assert_eq!(
+            config.start_order().unwrap_err().to_string(),
+            "Daemon 'web' depends on unknown daemon 'db'"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Self-checks of the environment attyvo runs in, for `attyvo doctor`.
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{
+    CreateLock, DaemonPaths, create_files, daemon_status, ensure_pipe_dir_exists, list_daemons,
+    procfs, remove_daemon_files,
+};
+
+/// How a check turned out.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Pass,
+    /// Something works less well than it could, but daemons can run.
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check, with a hint on how to fix it when it did not pass.
+pub struct Check {
+    pub label: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(label: &'static str, detail: String) -> Check {
+        Check {
+            label,
+            status: Status::Pass,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn problem(label: &'static str, status: Status, detail: String, hint: String) -> Check {
+        Check {
+            label,
+            status,
+            detail,
+            hint: Some(hint),
+        }
+    }
+}
+
+/// Runs every check against the pipe directory `dir`. Checks that need a usable
+/// directory are skipped when it is not.
+pub fn run(dir: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    match ensure_pipe_dir_exists(dir) {
+        Ok(()) => checks.push(Check::pass(
+            "pipe directory",
+            format!("{} is ours and not writable by others", dir.display()),
+        )),
+        Err(e) => {
+            checks.push(Check::problem(
+                "pipe directory",
+                Status::Fail,
+                format!("{:#}", e),
+                "Fix it as above, or point --dir or ATTYVO_DIR at a directory you own".to_string(),
+            ));
+            return checks;
+        }
+    }
+    checks.push(check_probe_files(dir));
+    checks.push(check_pty());
+    checks.push(check_procfs());
+    checks.extend(check_leftovers(dir));
+    checks
+}
+
+/// Creates and exercises the files a daemon needs, under a name no daemon uses.
+fn check_probe_files(dir: &Path) -> Check {
+    const LABEL: &str = "named pipes and locks";
+    let name = format!(".doctor-{}", std::process::id());
+    let paths = DaemonPaths::new(dir, &name);
+    let result = probe_files(dir, &paths, &name);
+    remove_daemon_files(&paths).ok();
+    match result {
+        Ok(()) => Check::pass(
+            LABEL,
+            "FIFOs can be created, written and read; flock works".to_string(),
+        ),
+        Err(e) => Check::problem(
+            LABEL,
+            Status::Fail,
+            format!("{:#}", e),
+            "Use a pipe directory on a local filesystem such as /tmp, e.g. --dir /tmp/attyvo-$USER"
+                .to_string(),
+        ),
+    }
+}
+
+fn probe_files(dir: &Path, paths: &DaemonPaths, name: &str) -> Result<()> {
+    let _lock = CreateLock::acquire(paths, name)?;
+    create_files(dir, paths, 0o600)?;
+    let mut fifo = File::options()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&paths.stdin)?;
+    fifo.write_all(b"x")?;
+    let mut byte = [0u8; 1];
+    fifo.read_exact(&mut byte)?;
+    Ok(())
+}
+
+fn check_pty() -> Check {
+    const LABEL: &str = "pseudo-terminals";
+    match pty_process::blocking::open() {
+        Ok(_) => Check::pass(LABEL, "a PTY can be opened".to_string()),
+        Err(e) => Check::problem(
+            LABEL,
+            Status::Fail,
+            format!("cannot open a PTY: {}", e),
+            "Make sure /dev/ptmx exists and /dev/pts is mounted, e.g. in containers".to_string(),
+        ),
+    }
+}
+
+fn check_procfs() -> Check {
+    const LABEL: &str = "/proc";
+    if procfs::available() {
+        return Check::pass(LABEL, "available".to_string());
+    }
+    Check::problem(
+        LABEL,
+        Status::Warn,
+        "not available, so `top` shows no usage and a reused PID cannot be told apart".to_string(),
+        "Mount procfs on /proc if this system has it".to_string(),
+    )
+}
+
+/// Finds daemons that died without a trace and pipes that no daemon owns.
+fn check_leftovers(dir: &Path) -> Vec<Check> {
+    const LABEL: &str = "leftover files";
+    let mut problems = Vec::new();
+    for name in list_daemons(dir).map(|daemons| daemons.names).unwrap_or_default() {
+        if daemon_status(dir, &name).is_ok_and(|status| status.state() == "dead") {
+            problems.push(Check::problem(
+                LABEL,
+                Status::Warn,
+                format!("daemon '{}' died without recording how it exited", name),
+                "Run `attyvo prune` to remove the files of dead daemons".to_string(),
+            ));
+        }
+    }
+    let entries = std::fs::read_dir(dir).into_iter().flatten().flatten();
+    for entry in entries {
+        let file_name = entry.file_name();
+        let Some(name) = file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix("_stdin"))
+        else {
+            continue;
+        };
+        let is_fifo = entry.file_type().is_ok_and(|file_type| file_type.is_fifo());
+        if is_fifo && !DaemonPaths::new(dir, name).pid.exists() {
+            problems.push(Check::problem(
+                LABEL,
+                Status::Warn,
+                format!("pipes of '{}' are there without a PID file", name),
+                format!(
+                    "Run `attyvo kill --force {}` unless it is being created right now",
+                    name
+                ),
+            ));
+        }
+    }
+    if problems.is_empty() {
+        problems.push(Check::pass(LABEL, "none".to_string()));
+    }
+    problems
+}
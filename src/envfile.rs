@@ -0,0 +1,144 @@
+//! Environment variables loaded from dotenv-style files, for `create --env-file`.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+/// Reads the variables defined in the file at `path`, in the order they appear.
+pub fn load(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read env file {}: {}", path.display(), e))?;
+    parse(&contents).map_err(|e| anyhow::anyhow!("Invalid env file {}: {}", path.display(), e))
+}
+
+/// Parses `KEY=VALUE` lines. Blank lines and lines starting with `#` are
+/// skipped, and a leading `export ` is allowed. Values may be quoted: single
+/// quotes keep everything as is, double quotes understand `\n`, `\t`, `\r`,
+/// `\"` and `\\`. Unquoted values end at a ` #` comment and are trimmed.
+pub fn parse(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected KEY=VALUE", number + 1));
+        };
+        let key = key.trim();
+        let valid_key = key.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            return Err(format!(
+                "line {}: invalid variable name '{}'",
+                number + 1,
+                key
+            ));
+        }
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    let quote = match value.chars().next() {
+        Some(quote @ ('\'' | '"')) => quote,
+        _ => {
+            let end = value.find(" #").unwrap_or(value.len());
+            return Ok(value[..end].trim_end().to_string());
+        }
+    };
+    let mut parsed = String::new();
+    let mut chars = value[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c == quote => {
+                let rest = chars.as_str().trim_start();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(format!("unexpected '{}' after closing quote", rest));
+                }
+                return Ok(parsed);
+            }
+            '\\' if quote == '"' => match chars.next() {
+                Some('n') => parsed.push('\n'),
+                Some('t') => parsed.push('\t'),
+                Some('r') => parsed.push('\r'),
+                Some(c @ ('"' | '\\')) => parsed.push(c),
+                Some(c) => {
+                    parsed.push('\\');
+                    parsed.push(c);
+                }
+                None => break,
+            },
+            c => parsed.push(c),
+        }
+    }
+    Err(format!("missing closing {}", quote))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_plain_lines_comments_and_export() {
+        let contents = "# settings\n\nPORT=8080\nexport HOST = localhost \nEMPTY=\n";
+        assert_eq!(
+            parse(contents).unwrap(),
+            vars(&[("PORT", "8080"), ("HOST", "localhost"), ("EMPTY", "")])
+        );
+    }
+
+    #[test]
+    fn unquoted_values_end_at_a_comment() {
+        assert_eq!(
+            parse("A=one # note\nB=two#three").unwrap(),
+            vars(&[("A", "one"), ("B", "two#three")])
+        );
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let contents = r#"SINGLE='a \n b # c'
+DOUBLE="line\nnext\t\"quoted\" \\ \x"
+TRAILING="value" # comment"#;
+        assert_eq!(
+            parse(contents).unwrap(),
+            vars(&[
+                ("SINGLE", r"a \n b # c"),
+                ("DOUBLE", "line\nnext\t\"quoted\" \\ \\x"),
+                ("TRAILING", "value"),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(
+            parse("A=1\nNOVALUE").unwrap_err(),
+            "line 2: expected KEY=VALUE"
+        );
+        assert_eq!(
+            parse("1A=x").unwrap_err(),
+            "line 1: invalid variable name '1A'"
+        );
+        assert_eq!(
+            parse("A-B=x").unwrap_err(),
+            "line 1: invalid variable name 'A-B'"
+        );
+        assert_eq!(parse("A=\"open").unwrap_err(), "line 1: missing closing \"");
+        assert_eq!(
+            parse("A='x' y").unwrap_err(),
+            "line 1: unexpected 'y' after closing quote"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Failures that the CLI reports with exit codes of their own, so that scripts
+//! can tell them apart without parsing error messages.
+
+use std::fmt;
+
+/// Exit code for failures without a more specific one.
+pub const GENERAL: i32 = 1;
+/// Exit code when the named daemon does not exist.
+pub const NOT_FOUND: i32 = 3;
+/// Exit code when the daemon exists but its command is not running.
+pub const NOT_RUNNING: i32 = 4;
+/// Exit code when a daemon of that name already exists.
+pub const ALREADY_EXISTS: i32 = 5;
+/// Exit code when the operating system refused access to a file or process.
+pub const PERMISSION_DENIED: i32 = 6;
+/// Exit code when `read --require-output` found nothing to read.
+pub const NO_OUTPUT: i32 = 7;
+/// Exit code when the daemon did not open its end of a pipe in time.
+pub const NOT_RESPONDING: i32 = 8;
+/// Exit code when the daemon's command exists but cannot be executed, as in shells.
+pub const NOT_EXECUTABLE: i32 = 126;
+/// Exit code when the daemon's command cannot be found, as in shells.
+pub const COMMAND_NOT_FOUND: i32 = 127;
+
+/// A failure concerning the daemon, or for the last two the command, of the given name.
+#[derive(Debug)]
+pub enum Failure {
+    NotFound(String),
+    NotRunning(String),
+    AlreadyExists(String),
+    NoOutput(String),
+    NotResponding(String),
+    NotExecutable(String),
+    CommandNotFound(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::NotFound(name) => write!(f, "Daemon '{}' does not exist", name),
+            Failure::NotRunning(name) => write!(f, "Process {} is not running", name),
+            Failure::AlreadyExists(name) => write!(f, "Daemon '{}' already exists", name),
+            Failure::NoOutput(name) => write!(f, "Daemon '{}' had no output to read", name),
+            Failure::NotResponding(name) => write!(f, "Daemon '{}' is not responding", name),
+            Failure::NotExecutable(command) => write!(f, "Command is not executable: {}", command),
+            Failure::CommandNotFound(command) => write!(f, "Command not found: {}", command),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The exit code for `error`, going by the first cause that has one.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return match failure {
+                Failure::NotFound(_) => NOT_FOUND,
+                Failure::NotRunning(_) => NOT_RUNNING,
+                Failure::AlreadyExists(_) => ALREADY_EXISTS,
+                Failure::NoOutput(_) => NO_OUTPUT,
+                Failure::NotResponding(_) => NOT_RESPONDING,
+                Failure::NotExecutable(_) => NOT_EXECUTABLE,
+                Failure::CommandNotFound(_) => COMMAND_NOT_FOUND,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>()
+            && e.kind() == std::io::ErrorKind::PermissionDenied
+        {
+            return PERMISSION_DENIED;
+        }
+    }
+    GENERAL
+}
@@ -0,0 +1,200 @@
+//! Lifecycle events, published as JSON lines on the `events.sock` stream socket
+//! in the pipe directory.
+//!
+//! One process at a time listens on the socket: whichever running daemon, or
+//! `attyvo events`, took the hub lock first. It repeats every line a connection
+//! sends to all the other connections, so publishers connect just long enough
+//! to send one event while monitors stay connected and read.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    os::{
+        fd::AsRawFd,
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Result;
+
+/// How many events may queue up for a client that reads slowly before newer
+/// ones are dropped for it, so publishing never holds anything up.
+const CLIENT_QUEUE: usize = 256;
+
+/// The socket that clients of the daemons in `dir` connect to.
+pub fn socket_path(dir: &Path) -> PathBuf {
+    dir.join("events.sock")
+}
+
+/// Held by the process serving the socket. Names have no dots, so this cannot
+/// be a daemon's file.
+fn lock_path(dir: &Path) -> PathBuf {
+    dir.join("events.sock.lock")
+}
+
+/// The connected clients while this process serves the socket, each with a
+/// queue its own thread writes to it from.
+#[derive(Default)]
+struct Hub {
+    clients: Mutex<Vec<Client>>,
+    /// Events queued but not yet written to their client.
+    pending: AtomicUsize,
+}
+
+struct Client {
+    id: u64,
+    queue: mpsc::SyncSender<Arc<[u8]>>,
+}
+
+static HUB: OnceLock<Hub> = OnceLock::new();
+
+impl Hub {
+    /// Queues `line` for every client but `from`, dropping clients that are gone.
+    fn broadcast(&self, from: Option<u64>, line: Arc<[u8]>) {
+        self.clients.lock().unwrap().retain(|client| {
+            if Some(client.id) == from {
+                return true;
+            }
+            match client.queue.try_send(Arc::clone(&line)) {
+                Ok(()) => {
+                    self.pending.fetch_add(1, Ordering::SeqCst);
+                    true
+                }
+                Err(mpsc::TrySendError::Full(_)) => true,
+                Err(mpsc::TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    fn write_to(&self, mut client: UnixStream, queue: mpsc::Receiver<Arc<[u8]>>) {
+        for line in &queue {
+            let written = client.write_all(&line);
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            if written.is_err() {
+                break;
+            }
+        }
+        // The client is gone; whatever was still queued for it never will be written.
+        let dropped = queue.try_iter().count();
+        self.pending.fetch_sub(dropped, Ordering::SeqCst);
+    }
+}
+
+/// Sends `event` about daemon `name`, with `details` merged into it, to every
+/// connected client. Nothing is sent while no process serves the socket.
+pub fn publish(dir: &Path, name: &str, event: &str, details: serde_json::Value) {
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let mut message = serde_json::json!({ "time": millis, "event": event, "name": name });
+    if let (Some(message), serde_json::Value::Object(details)) = (message.as_object_mut(), details)
+    {
+        message.extend(details);
+    }
+    let line = format!("{}\n", message);
+    // Going through our own socket would race with this process exiting.
+    if let Some(hub) = HUB.get() {
+        hub.broadcast(None, line.into_bytes().into());
+        return;
+    }
+    let Ok(mut socket) = UnixStream::connect(socket_path(dir)) else {
+        return;
+    };
+    socket.write_all(line.as_bytes()).ok();
+}
+
+/// Waits up to `timeout` for the events this process queued as the server of
+/// the socket to reach their clients, for a daemon about to exit.
+pub fn flush(timeout: Duration) {
+    let Some(hub) = HUB.get() else {
+        return;
+    };
+    let deadline = Instant::now() + timeout;
+    while hub.pending.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Waits until no other process serves the socket, then serves it until that
+/// fails. Meant for a thread of its own; when the process exits, the lock is
+/// released and another daemon or listener takes over.
+pub fn serve(dir: &Path) -> Result<()> {
+    // Held until this returns, which is normally when the process exits.
+    let lock = File::create(lock_path(dir))?;
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let path = socket_path(dir);
+    // The previous hub is gone, but its socket file may not be.
+    if let Err(e) = std::fs::remove_file(&path)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        return Err(e.into());
+    }
+    let listener = UnixListener::bind(&path)?;
+    log::debug!("Serving events on {}", path.display());
+
+    let hub = HUB.get_or_init(Hub::default);
+    for (id, stream) in (0..).zip(listener.incoming()) {
+        let stream = stream?;
+        let (queue, queued) = mpsc::sync_channel(CLIENT_QUEUE);
+        let writer = stream.try_clone()?;
+        std::thread::spawn(move || hub.write_to(writer, queued));
+        hub.clients.lock().unwrap().push(Client { id, queue });
+        std::thread::spawn(move || {
+            for line in BufReader::new(stream).split(b'\n') {
+                let Ok(mut line) = line else {
+                    break;
+                };
+                line.push(b'\n');
+                hub.broadcast(Some(id), line.into());
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Connects to the socket and hands every event to `on_event` as a JSON string
+/// until it returns an error. Serves the socket itself whenever no daemon
+/// does, and reconnects when the process serving it goes away, so events keep
+/// coming as daemons come and go.
+pub fn listen(dir: &Path, mut on_event: impl FnMut(&str) -> Result<()>) -> Result<()> {
+    let hub_dir = dir.to_path_buf();
+    std::thread::spawn(move || {
+        if let Err(e) = serve(&hub_dir) {
+            log::debug!("Cannot serve events in {}: {}", hub_dir.display(), e);
+        }
+    });
+    let path = socket_path(dir);
+    loop {
+        match UnixStream::connect(&path) {
+            Ok(socket) => {
+                for line in BufReader::new(socket).split(b'\n') {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    on_event(&String::from_utf8_lossy(&line))?;
+                }
+                log::debug!("Events hub went away; reconnecting");
+            }
+            // Another process takes over the socket shortly.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
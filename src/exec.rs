@@ -0,0 +1,165 @@
+//! One-shot runs of a command under a transient daemon, for using attyvo as a
+//! plain PTY wrapper.
+
+use std::{
+    fs::File,
+    io::{IsTerminal, Read, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use crate::{
+    DEFAULT_KILL_TIMEOUT, DaemonMeta, DaemonPaths, FIFO_OPEN_TIMEOUT, FifoAccess,
+    attach::RawMode,
+    kill_daemon, load_exit,
+    logfile::{self, Stream},
+    open_fifo_with_timeout, poll_timeout_ms, send_eof, spawn_daemon,
+};
+
+/// Runs `meta`'s command as a daemon named after this process, wiring our own
+/// stdin, stdout and stderr to it until it exits. The daemon's files are removed
+/// afterwards and the command's exit code is returned.
+pub fn exec(dir: &Path, meta: &DaemonMeta) -> Result<i32> {
+    let name = format!("exec-{}", std::process::id());
+    let paths = DaemonPaths::new(dir, &name);
+    let (child, files) = spawn_daemon(dir, &name, meta)?;
+    let mut outputs = [
+        (open_for_reading(&name, &paths.stdout)?, Output::Stdout),
+        (open_for_reading(&name, &paths.stderr)?, Output::Stderr),
+    ];
+    drop(files);
+    let result = relay(dir, &name, child, &paths, &mut outputs);
+    // A command that could not be started has no output of its own to show why.
+    if let Ok(contents) = logfile::read_all(&paths.merged_log) {
+        for record in logfile::parse_records(&contents) {
+            if record.stream == Stream::Sys && record.line.starts_with(b"Failed to start") {
+                eprintln!("{}", String::from_utf8_lossy(&record.line));
+            }
+        }
+    }
+    kill_daemon(dir, &name, DEFAULT_KILL_TIMEOUT, true, false).ok();
+    result
+}
+
+#[derive(Clone, Copy)]
+enum Output {
+    Stdout,
+    Stderr,
+}
+
+fn open_for_reading(name: &str, path: &Path) -> Result<File> {
+    open_fifo_with_timeout(name, path, FifoAccess::Read, FIFO_OPEN_TIMEOUT)
+}
+
+fn relay(
+    dir: &Path,
+    name: &str,
+    child: libc::pid_t,
+    paths: &DaemonPaths,
+    outputs: &mut [(File, Output)],
+) -> Result<i32> {
+    let mut status = 0;
+    unsafe { libc::waitpid(child, &mut status, 0) };
+    if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+        return Err(anyhow::anyhow!("Command failed to start"));
+    }
+
+    let _raw_mode = std::io::stdin()
+        .is_terminal()
+        .then(RawMode::enable)
+        .flatten();
+    forward_stdin(dir, name, paths);
+    let exit = loop {
+        for (fifo, output) in outputs.iter_mut() {
+            copy_available(fifo, *output)?;
+        }
+        if let Ok(exit) = load_exit(paths) {
+            break exit;
+        }
+        wait_readable_any(outputs, Duration::from_millis(100));
+    };
+    // The daemon may still be moving the last output into the pipes.
+    while wait_readable_any(outputs, Duration::from_millis(50)) {
+        for (fifo, output) in outputs.iter_mut() {
+            copy_available(fifo, *output)?;
+        }
+    }
+    Ok(exit.exit_code())
+}
+
+/// Copies our stdin to the daemon's from a background thread, passing on EOF
+/// when stdin is not a terminal.
+fn forward_stdin(dir: &Path, name: &str, paths: &DaemonPaths) {
+    let (dir, name) = (dir.to_path_buf(), name.to_string());
+    let (stdin_path, pid_path, exit_path) =
+        (paths.stdin.clone(), paths.pid.clone(), paths.exit.clone());
+    std::thread::spawn(move || {
+        let opened =
+            open_fifo_with_timeout(&name, &stdin_path, FifoAccess::Write, FIFO_OPEN_TIMEOUT);
+        let Ok(mut daemon_stdin) = opened else {
+            return;
+        };
+        let interactive = std::io::stdin().is_terminal();
+        let mut keyboard = std::io::stdin();
+        let mut buf = [0u8; 1024];
+        loop {
+            match keyboard.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if daemon_stdin.write_all(&buf[..n]).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        if !interactive {
+            // The daemon only takes control messages once its command is running.
+            while !pid_path.exists() && !exit_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            send_eof(&dir, &name).ok();
+        }
+    });
+}
+
+/// Moves whatever is buffered in `fifo` to our own stdout or stderr.
+fn copy_available(fifo: &mut File, output: Output) -> Result<()> {
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match fifo.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        match output {
+            Output::Stdout => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&buf[..n])?;
+                stdout.flush()?;
+            }
+            Output::Stderr => std::io::stderr().write_all(&buf[..n])?,
+        }
+    }
+}
+
+/// Waits up to `timeout` for any of the pipes to have output, returning false
+/// if none did.
+fn wait_readable_any(outputs: &[(File, Output)], timeout: Duration) -> bool {
+    let mut fds: Vec<libc::pollfd> = outputs
+        .iter()
+        .map(|(fifo, _)| libc::pollfd {
+            fd: fifo.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout_ms = poll_timeout_ms(Some(timeout));
+    unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+    // Once the daemon is gone the pipes report a hangup, which is not output.
+    fds.iter().any(|fd| fd.revents & libc::POLLIN != 0)
+}
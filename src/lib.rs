@@ -0,0 +1,3429 @@
+//! attyvo runs commands as background daemons under a pseudo-terminal, with
+//! their stdin, stdout and stderr exposed as named pipes in a shared directory.
+//!
+//! [`DaemonManager`] drives those daemons from Rust; the `attyvo` binary is a
+//! thin command line over the same code.
+
+mod ansi;
+mod attach;
+pub mod cli;
+mod config;
+mod doctor;
+mod envfile;
+mod error;
+mod events;
+mod exec;
+mod logfile;
+mod logging;
+mod manager;
+mod pipe;
+mod procfs;
+mod ring;
+mod send;
+mod watch;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    os::{
+        fd::{AsFd, AsRawFd, OwnedFd},
+        unix::{
+            fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt},
+            process::{CommandExt, ExitStatusExt},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use daemonize_me::Daemon;
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+
+pub use crate::{error::Failure, manager::DaemonManager};
+use crate::{
+    logfile::{LogTail, MergedLog, Record, RotatingLog, Stream},
+    ring::RingBuffer,
+};
+
+struct DaemonFiles {
+    stdin: File,
+    stdout: File,
+    stderr: File,
+    control: File,
+}
+
+/// Locations of the files backing a single daemon inside the pipe directory.
+struct DaemonPaths {
+    name: String,
+    stdin: PathBuf,
+    stdout: PathBuf,
+    stderr: PathBuf,
+    control: PathBuf,
+    pid: PathBuf,
+    meta: PathBuf,
+    exit: PathBuf,
+    lock: PathBuf,
+    stdout_log: PathBuf,
+    stderr_log: PathBuf,
+    merged_log: PathBuf,
+    health: PathBuf,
+    daemon_log: PathBuf,
+}
+
+impl DaemonPaths {
+    fn new(dir: &Path, name: &str) -> Self {
+        DaemonPaths {
+            name: name.to_string(),
+            stdin: dir.join(format!("{}_stdin", name)),
+            stdout: dir.join(format!("{}_stdout", name)),
+            stderr: dir.join(format!("{}_stderr", name)),
+            control: dir.join(format!("{}_control", name)),
+            pid: dir.join(format!("{}.pid", name)),
+            meta: dir.join(format!("{}.json", name)),
+            exit: dir.join(format!("{}.exit", name)),
+            lock: dir.join(format!("{}.lock", name)),
+            stdout_log: dir.join(format!("{}.stdout.log", name)),
+            stderr_log: dir.join(format!("{}.stderr.log", name)),
+            merged_log: dir.join(format!("{}.log", name)),
+            health: dir.join(format!("{}.health", name)),
+            daemon_log: dir.join(format!("{}.attyvo.log", name)),
+        }
+    }
+
+    /// The paths other tools may want to use, labelled as shown by `attyvo paths`.
+    fn labelled(&self) -> Vec<(&'static str, &Path)> {
+        vec![
+            ("stdin", &self.stdin),
+            ("stdout", &self.stdout),
+            ("stderr", &self.stderr),
+            ("control", &self.control),
+            ("pid", &self.pid),
+            ("meta", &self.meta),
+            ("exit", &self.exit),
+            ("health", &self.health),
+            ("log", &self.merged_log),
+            ("stdout_log", &self.stdout_log),
+            ("stderr_log", &self.stderr_log),
+            ("attyvo_log", &self.daemon_log),
+        ]
+    }
+
+    /// Every file that may belong to the daemon, including rotated logs and the
+    /// files of relays from its output.
+    fn all(&self) -> Vec<PathBuf> {
+        let mut paths = vec![
+            self.stdin.clone(),
+            self.stdout.clone(),
+            self.stderr.clone(),
+            self.control.clone(),
+            self.pid.clone(),
+            self.meta.clone(),
+            self.exit.clone(),
+            self.lock.clone(),
+            self.health.clone(),
+            self.stdout_log.clone(),
+            logfile::rotated_path(&self.stdout_log),
+            self.stderr_log.clone(),
+            logfile::rotated_path(&self.stderr_log),
+            self.merged_log.clone(),
+            logfile::rotated_path(&self.merged_log),
+            self.daemon_log.clone(),
+        ];
+        paths.extend(self.relays());
+        paths
+    }
+
+    /// Where `read` keeps the cursor of `consumer`.
+    fn offset(&self, consumer: &str) -> PathBuf {
+        self.pid.with_file_name(format!("{}.{}.offset", self.name, consumer))
+    }
+
+    /// Where the relay feeding this daemon's output into daemon `dst` records itself.
+    fn relay(&self, dst: &str) -> PathBuf {
+        self.pid.with_file_name(format!("{}.{}.relay", self.name, dst))
+    }
+
+    /// The files of the relays feeding this daemon's output into other daemons.
+    fn relays(&self) -> Vec<PathBuf> {
+        let Some(entries) = self.pid.parent().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        let prefix = format!("{}.", self.name);
+        entries
+            .filter_map(|entry| {
+                let file_name = entry.ok()?.file_name().into_string().ok()?;
+                let dst = file_name.strip_prefix(&prefix)?.strip_suffix(".relay")?;
+                // Names have no dots, so `a.b.c.relay` cannot belong to daemon `a`.
+                validate_name(dst).is_ok().then(|| self.relay(dst))
+            })
+            .collect()
+    }
+
+    /// The consumers with a cursor on this daemon's output, and their cursor files.
+    fn offsets(&self) -> Vec<(String, PathBuf)> {
+        let Some(entries) = self.pid.parent().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        let prefix = format!("{}.", self.name);
+        entries
+            .filter_map(|entry| {
+                let file_name = entry.ok()?.file_name().into_string().ok()?;
+                let consumer = file_name.strip_prefix(&prefix)?.strip_suffix(".offset")?;
+                // Consumer IDs have no dots, so `a.b.c.offset` cannot belong to daemon `a`.
+                (validate_consumer(consumer).is_ok())
+                    .then(|| (consumer.to_string(), self.offset(consumer)))
+            })
+            .collect()
+    }
+}
+
+/// Information about how a daemon was started, persisted next to its PID file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DaemonMeta {
+    /// Program to run, looked up on the `PATH` unless it contains a slash.
+    pub command: String,
+    /// Arguments passed to the program.
+    pub args: Vec<String>,
+    /// Seconds since the Unix epoch at which the daemon was created.
+    started_at: u64,
+    /// Height of the command's terminal.
+    #[serde(default = "default_rows")]
+    pub rows: u16,
+    /// Width of the command's terminal.
+    #[serde(default = "default_cols")]
+    pub cols: u16,
+    /// Environment variables set on the command, on top of the inherited environment.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Whether the command starts from an empty environment instead of inheriting one.
+    #[serde(default)]
+    pub env_clear: bool,
+    /// Dotenv file read for more environment variables every time the command
+    /// starts, so their values never end up in the metadata. `env` wins over it.
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+    /// Names of the variables `env_file` defined when the daemon was created, for display.
+    #[serde(default)]
+    pub env_file_keys: Vec<String>,
+    /// `TERM` the command runs with; unknown for daemons created before it was
+    /// set, and left alone without a PTY.
+    #[serde(default)]
+    pub term: Option<String>,
+    /// Working directory of the command, already resolved to an absolute path.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// Size in bytes after which the stdout and stderr logs are rotated.
+    #[serde(default = "default_log_max_size")]
+    pub log_max_size: u64,
+    /// Bytes of stdout and of stderr each kept in memory for readers that are not there yet.
+    #[serde(default = "default_max_output_buffer")]
+    pub max_output_buffer: u64,
+    /// Permission bits the FIFOs are created with.
+    #[serde(default = "default_fifo_mode")]
+    pub fifo_mode: u32,
+    /// User the command runs as, when different from the daemon's own.
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// Group the command runs as, when different from the daemon's own.
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// When the command is started again after it exits.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// Delay before the first restart; it doubles with every further attempt.
+    #[serde(default = "default_restart_delay")]
+    pub restart_delay: u64,
+    /// How many times the command is restarted before the daemon gives up.
+    #[serde(default = "default_restart_max")]
+    pub restart_max: u32,
+    /// How often the command has been restarted so far, kept up to date by the daemon.
+    #[serde(default)]
+    restarts: u32,
+    /// How the previous run of the command ended, if it was restarted.
+    #[serde(default)]
+    last_exit: Option<ExitInfo>,
+    /// Set once the daemon gave up restarting after `restart_max` attempts.
+    #[serde(default)]
+    failed: bool,
+    /// Shell command run once the command has exited for good.
+    #[serde(default)]
+    pub on_exit: Option<String>,
+    /// Start time of the process in the PID file, in clock ticks since boot, as
+    /// reported by `/proc`. Tells the command apart from a process reusing its PID.
+    #[serde(default)]
+    pid_start_time: Option<u64>,
+    /// Seconds after which the watchdog stops the command for good.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// Whether the watchdog had to stop the command.
+    #[serde(default)]
+    timed_out: bool,
+    /// Seconds without input after which the daemon shuts itself down and cleans up.
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// Set by the daemon once its command has outlived `START_CONFIRM_DELAY`.
+    #[serde(default)]
+    started_ok: bool,
+    /// Whether `pause` froze the command and `resume` has not let it go on yet.
+    /// A restarted command starts out running, so the daemon clears it.
+    #[serde(default)]
+    paused: bool,
+    /// PID and start time of the daemon process supervising the command. Only the
+    /// supervisor recorded here may restart the command, so one that outlived a
+    /// `kill` cannot take over a new daemon of the same name.
+    #[serde(default)]
+    supervisor: Option<(i32, Option<u64>)>,
+    /// Shell command that exits successfully while the daemon is healthy.
+    #[serde(default)]
+    pub health_cmd: Option<String>,
+    /// Seconds between health checks.
+    #[serde(default = "default_health_interval")]
+    pub health_interval: u64,
+    /// Failed checks in a row after which a supervised command is restarted.
+    #[serde(default = "default_health_retries")]
+    pub health_retries: u32,
+    /// What happens to the stdout and stderr logs each time the command is (re)started.
+    #[serde(default)]
+    pub log_policy: LogPolicy,
+    /// Whether the PTY hands input to the command by the line or by the byte.
+    #[serde(default)]
+    pub stdin_mode: StdinMode,
+    /// Whether the command runs on plain pipes instead of a PTY, so its input and
+    /// output pass through byte for byte, without echo or `\r\n` line ends.
+    #[serde(default)]
+    pub no_pty: bool,
+    /// Shell that runs the command line through `-c`, instead of running the command directly.
+    #[serde(default)]
+    pub shell: Option<String>,
+    /// Cap on the command's address space, in bytes.
+    #[serde(default)]
+    pub limit_as: Option<u64>,
+    /// Cap on the command's CPU time, in seconds.
+    #[serde(default)]
+    pub limit_cpu: Option<u64>,
+    /// Cap on the number of files the command may have open.
+    #[serde(default)]
+    pub limit_nofile: Option<u64>,
+}
+
+/// How the PTY's line discipline passes input on to the command. Programs can
+/// still switch modes themselves, as full-screen ones usually do.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StdinMode {
+    /// Canonical mode: input arrives a complete line at a time and can be edited
+    #[default]
+    Line,
+    /// Non-canonical mode: every byte arrives as soon as it is written
+    Char,
+}
+
+/// Whether the stdout and stderr logs keep the output of earlier runs of the command.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogPolicy {
+    /// Keep it, with a marker line where each new run starts
+    #[default]
+    Append,
+    /// Start every run with an empty log
+    Truncate,
+}
+
+impl LogPolicy {
+    /// Prepares `log` for a new run of the command.
+    fn start_run(self, log: &mut RotatingLog) -> std::io::Result<()> {
+        match self {
+            LogPolicy::Truncate => log.truncate(),
+            LogPolicy::Append if log.is_empty() => Ok(()),
+            LogPolicy::Append => {
+                let marker = format!("--- restart at {} ---\n", format_timestamp(unix_millis()));
+                log.write(marker.as_bytes())
+            }
+        }
+    }
+}
+
+/// Whether the daemon starts its command again after it exits.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Never restart
+    #[default]
+    No,
+    /// Restart when the command exits with a non-zero code or is killed by a signal
+    OnFailure,
+    /// Restart whenever the command exits
+    Always,
+}
+
+impl RestartPolicy {
+    fn should_restart(self, status: &std::process::ExitStatus) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure => !status.success(),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// How the daemon's command terminated, written by the daemon just before it exits.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExitInfo {
+    /// Exit code, if the command exited normally.
+    pub code: Option<i32>,
+    /// Number of the signal that killed the command, if one did.
+    pub signal: Option<i32>,
+}
+
+impl ExitInfo {
+    /// The status a shell would report: the exit code, or 128 + the signal number.
+    pub fn exit_code(&self) -> i32 {
+        match (self.code, self.signal) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for ExitInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exited with code {}", code),
+            (None, Some(signal)) => write!(f, "killed by signal {}", signal),
+            (None, None) => write!(f, "exited"),
+        }
+    }
+}
+
+fn default_log_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_output_buffer() -> u64 {
+    1024 * 1024
+}
+
+fn default_fifo_mode() -> u32 {
+    0o600
+}
+
+fn default_restart_delay() -> u64 {
+    1
+}
+
+fn default_restart_max() -> u32 {
+    10
+}
+
+fn default_health_interval() -> u64 {
+    30
+}
+
+fn default_health_retries() -> u32 {
+    3
+}
+
+fn default_rows() -> u16 {
+    24
+}
+
+fn default_cols() -> u16 {
+    80
+}
+
+impl DaemonMeta {
+    /// Settings for running `command` with `args`, with every option at its default.
+    pub fn new(command: String, args: Vec<String>) -> Self {
+        DaemonMeta {
+            command,
+            args,
+            started_at: unix_now(),
+            rows: default_rows(),
+            cols: default_cols(),
+            env: BTreeMap::new(),
+            env_clear: false,
+            env_file: None,
+            env_file_keys: Vec::new(),
+            term: Some(DEFAULT_TERM.to_string()),
+            cwd: None,
+            log_max_size: default_log_max_size(),
+            max_output_buffer: default_max_output_buffer(),
+            fifo_mode: default_fifo_mode(),
+            uid: None,
+            gid: None,
+            restart: RestartPolicy::No,
+            restart_delay: default_restart_delay(),
+            restart_max: default_restart_max(),
+            restarts: 0,
+            last_exit: None,
+            failed: false,
+            on_exit: None,
+            pid_start_time: None,
+            timeout: None,
+            timed_out: false,
+            idle_timeout: None,
+            started_ok: false,
+            paused: false,
+            supervisor: None,
+            health_cmd: None,
+            health_interval: default_health_interval(),
+            health_retries: default_health_retries(),
+            log_policy: LogPolicy::Append,
+            stdin_mode: StdinMode::Line,
+            no_pty: false,
+            shell: None,
+            limit_as: None,
+            limit_cpu: None,
+            limit_nofile: None,
+        }
+    }
+
+    /// Forgets how earlier runs went, so the settings can start a daemon afresh.
+    fn reset_runs(&mut self) {
+        self.started_at = unix_now();
+        self.pid_start_time = None;
+        self.restarts = 0;
+        self.last_exit = None;
+        self.failed = false;
+        self.timed_out = false;
+        self.started_ok = false;
+        self.paused = false;
+        self.supervisor = None;
+    }
+
+    /// Whether the daemon process that supervises the command is still alive,
+    /// e.g. waiting to restart a command that exited.
+    fn supervisor_alive(&self) -> bool {
+        self.supervisor.is_some_and(|(pid, start_time)| {
+            is_process_alive(pid)
+                && (start_time.is_none() || procfs::start_time(pid) == start_time)
+        })
+    }
+
+    /// The variables `env_file` defines right now, if there is one.
+    fn file_env(&self) -> Result<Vec<(String, String)>> {
+        self.env_file.as_deref().map_or(Ok(Vec::new()), envfile::load)
+    }
+
+    /// The program the daemon executes: the shell, if there is one, or else the command.
+    fn program(&self) -> &str {
+        self.shell.as_deref().unwrap_or(&self.command)
+    }
+
+    /// The command and its arguments joined by spaces, for display.
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.command)
+            .chain(&self.args)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Runs daemon `name` until its command exits for good and returns how it ended.
+/// Unless `foreground` is set, the process first detaches from the terminal.
+fn start_daemon(
+    dir: &Path,
+    name: &str,
+    meta: &DaemonMeta,
+    files: DaemonFiles,
+    lock: CreateLock,
+    foreground: bool,
+) -> Result<ExitInfo> {
+    let result = run_daemon(dir, name, meta, files, lock, foreground);
+    // We may be serving the events socket; the last events are about us.
+    events::flush(EVENTS_FLUSH_TIMEOUT);
+    result
+}
+
+/// How long a daemon that is about to exit waits for its events to be sent.
+const EVENTS_FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn run_daemon(
+    dir: &Path,
+    name: &str,
+    meta: &DaemonMeta,
+    files: DaemonFiles,
+    lock: CreateLock,
+    foreground: bool,
+) -> Result<ExitInfo> {
+    let paths = DaemonPaths::new(dir, name);
+    if foreground {
+        // Taken by a thread below; blocked before any thread starts so that none
+        // of them gets the signals instead. The command starts with them unblocked.
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_BLOCK, &forwarded_signals(), std::ptr::null_mut());
+        }
+    } else {
+        Daemon::new().work_dir(".").start()?;
+        // Nothing is attached to stderr any more once detached.
+        logging::redirect(&paths.daemon_log);
+        log::debug!("Daemon '{}' detached", name);
+    }
+    let (terminal, mut pts) = if meta.no_pty {
+        (Terminal::Pipe(None), None)
+    } else {
+        let (pty, pts) = pty_process::blocking::open()?;
+        pty.resize(pty_process::Size::new(meta.rows, meta.cols))?;
+        (Terminal::Pty(Arc::new(pty)), Some(pts))
+    };
+    let stdout_log = Arc::new(Mutex::new(RotatingLog::open(&paths.stdout_log, meta.log_max_size)?));
+    let stderr_log = Arc::new(Mutex::new(RotatingLog::open(&paths.stderr_log, meta.log_max_size)?));
+    let merged_log = Arc::new(Mutex::new(MergedLog::open(&paths.merged_log, meta.log_max_size)?));
+
+    let input = Arc::new(PtyInput {
+        terminal: Mutex::new(terminal),
+        size: Mutex::new((meta.rows, meta.cols)),
+        stdin: files.stdin,
+        at_line_start: Mutex::new(true),
+        last_input: Mutex::new(Instant::now()),
+    });
+    {
+        let input = Arc::clone(&input);
+        std::thread::spawn(move || input.relay());
+    }
+    {
+        let dir = dir.to_path_buf();
+        std::thread::spawn(move || {
+            if let Err(e) = events::serve(&dir) {
+                let socket = events::socket_path(&dir);
+                log::error!("Cannot serve events on {}: {}", socket.display(), e);
+            }
+        });
+    }
+    let stdout_buffer = Arc::new(RingBuffer::new(meta.max_output_buffer as usize));
+    let stderr_buffer = Arc::new(RingBuffer::new(meta.max_output_buffer as usize));
+    for (buffer, fifo) in [(&stdout_buffer, files.stdout), (&stderr_buffer, files.stderr)] {
+        let buffer = Arc::clone(buffer);
+        std::thread::spawn(move || buffer.pump(fifo));
+    }
+    // Renaming moves the files on disk; the daemon only has to follow along.
+    let paths = Arc::new(Mutex::new(paths));
+    let stopping = Arc::new(AtomicBool::new(false));
+    {
+        let (control, dir, input) = (files.control, dir.to_path_buf(), Arc::clone(&input));
+        let (paths, stopping) = (Arc::clone(&paths), Arc::clone(&stopping));
+        let (stdout_log, merged_log) = (Arc::clone(&stdout_log), Arc::clone(&merged_log));
+        let stderr_log = Arc::clone(&stderr_log);
+        std::thread::spawn(move || {
+            handle_control(control, &input, &stopping, |new_name| {
+                let new_paths = DaemonPaths::new(&dir, new_name);
+                stdout_log.lock().unwrap().set_path(&new_paths.stdout_log);
+                stderr_log.lock().unwrap().set_path(&new_paths.stderr_log);
+                merged_log.lock().unwrap().set_path(&new_paths.merged_log);
+                *paths.lock().unwrap() = new_paths;
+            })
+        });
+    }
+
+    {
+        let (meta, paths) = (meta.clone(), Arc::clone(&paths));
+        let (merged_log, stopping) = (Arc::clone(&merged_log), Arc::clone(&stopping));
+        std::thread::spawn(move || check_health(&meta, &paths, &merged_log, &stopping));
+    }
+    let timed_out = Arc::new(AtomicBool::new(false));
+    if let Some(timeout) = meta.timeout {
+        let (paths, merged_log) = (Arc::clone(&paths), Arc::clone(&merged_log));
+        let (stopping, timed_out) = (Arc::clone(&stopping), Arc::clone(&timed_out));
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_secs(timeout));
+            let note = format!("Command ran for {}s; killed: timeout", timeout);
+            merged_log.lock().unwrap().note(&note).ok();
+            timed_out.store(true, Ordering::SeqCst);
+            stopping.store(true, Ordering::SeqCst);
+            stop_current_command(&paths, DEFAULT_KILL_TIMEOUT);
+        });
+    }
+    if let Some(idle_timeout) = meta.idle_timeout {
+        let (dir, input, paths) = (dir.to_path_buf(), Arc::clone(&input), Arc::clone(&paths));
+        let stopping = Arc::clone(&stopping);
+        std::thread::spawn(move || {
+            let idle_timeout = Duration::from_secs(idle_timeout);
+            loop {
+                let idle = input.last_input.lock().unwrap().elapsed();
+                if idle < idle_timeout {
+                    std::thread::sleep(idle_timeout - idle);
+                    continue;
+                }
+                stopping.store(true, Ordering::SeqCst);
+                shut_down_idle(&dir, &paths.lock().unwrap());
+                return;
+            }
+        });
+    }
+    if foreground {
+        let (paths, stopping) = (Arc::clone(&paths), Arc::clone(&stopping));
+        std::thread::spawn(move || forward_signals(&paths, &stopping));
+    }
+    let outputs = Outputs {
+        stdout_buffer,
+        stderr_buffer,
+        stdout_log,
+        stderr_log,
+        merged_log: Arc::clone(&merged_log),
+        mirror: foreground,
+    };
+    let mut meta = meta.clone();
+    let own_pid = std::process::id() as i32;
+    meta.supervisor = Some((own_pid, procfs::start_time(own_pid)));
+    let mut lock = Some(lock);
+    loop {
+        let meta = &mut meta;
+        let (attempt, current_name) = (meta.restarts, paths.lock().unwrap().name.clone());
+        let started = |pid| {
+            drop(lock.take());
+            match attempt {
+                0 => events::publish(dir, &current_name, "created", serde_json::json!({ "pid": pid })),
+                _ => events::publish(
+                    dir,
+                    &current_name,
+                    "restarted",
+                    serde_json::json!({ "pid": pid, "restarts": attempt }),
+                ),
+            }
+        };
+        meta.log_policy.start_run(&mut outputs.stdout_log.lock().unwrap())?;
+        meta.log_policy.start_run(&mut outputs.stderr_log.lock().unwrap())?;
+        let status = match run_command(meta, &paths, &input, pts.take(), &outputs, started) {
+            Ok(status) => status,
+            Err(e) => {
+                log::error!("Failed to start {}: {}", meta.command, e);
+                // Leave the same trail as a command that exited with a shell's
+                // "cannot execute" status, so status and kill still work.
+                merged_log
+                    .lock()
+                    .unwrap()
+                    .note(&format!("Failed to start {}: {}", meta.command, e))
+                    .ok();
+                let paths = paths.lock().unwrap();
+                if !paths.pid.exists() {
+                    write_atomic(&paths.pid, std::process::id().to_string().as_bytes())?;
+                }
+                let exit = ExitInfo {
+                    code: Some(127),
+                    signal: None,
+                };
+                write_atomic(&paths.exit, serde_json::to_string(&exit)?.as_bytes())?;
+                publish_exit(dir, &paths.name, &exit, false);
+                return Err(e);
+            }
+        };
+        let exit = ExitInfo {
+            code: status.code(),
+            signal: status.signal(),
+        };
+        // If the PID file is gone the daemon was killed and cleaned up, so there is
+        // nobody left to report the exit status to.
+        if !supervises(&paths.lock().unwrap()) {
+            run_exit_hook(meta, &exit, &merged_log);
+            return Ok(exit);
+        }
+        let restart =
+            meta.restart.should_restart(&status) && !stopping.load(Ordering::SeqCst);
+        log::debug!("Command {}; restarting: {}", exit, restart);
+        if restart && meta.restarts >= meta.restart_max {
+            merged_log
+                .lock()
+                .unwrap()
+                .note(&format!("Command {}; giving up after {} restarts", exit, meta.restarts))
+                .ok();
+            meta.failed = true;
+        }
+        meta.timed_out = timed_out.load(Ordering::SeqCst);
+        if !restart || meta.failed {
+            let paths = paths.lock().unwrap();
+            if meta.failed || meta.timed_out {
+                save_meta(&paths, meta)?;
+            }
+            write_atomic(&paths.exit, serde_json::to_string(&exit)?.as_bytes())?;
+            publish_exit(dir, &paths.name, &exit, meta.failed);
+            drop(paths);
+            run_exit_hook(meta, &exit, &merged_log);
+            return Ok(exit);
+        }
+        meta.restarts += 1;
+        let delay = restart_delay(meta.restart_delay, meta.restarts);
+        merged_log
+            .lock()
+            .unwrap()
+            .note(&format!(
+                "Command {}; restarting in {}s (attempt {}/{})",
+                exit,
+                delay.as_secs(),
+                meta.restarts,
+                meta.restart_max
+            ))
+            .ok();
+        meta.last_exit = Some(exit.clone());
+        save_meta(&paths.lock().unwrap(), meta)?;
+        std::thread::sleep(delay);
+        // Killing the daemon while it waits removes its files, and a new daemon may
+        // have been created under the same name since; do not come back to life.
+        if stopping.load(Ordering::SeqCst) || !supervises(&paths.lock().unwrap()) {
+            return Ok(exit);
+        }
+        if !meta.no_pty {
+            let (pty, new_pts) = pty_process::blocking::open()?;
+            input.replace_pty(pty);
+            pts = Some(new_pts);
+        }
+    }
+}
+
+/// Whether this process still supervises the daemon at `paths`: its PID file is
+/// there and its metadata names us, not the supervisor of a newer daemon that
+/// took over the name after a `kill`.
+fn supervises(paths: &DaemonPaths) -> bool {
+    let own_pid = std::process::id() as i32;
+    paths.pid.exists()
+        && load_meta(paths).is_ok_and(|meta| meta.supervisor.is_some_and(|(pid, _)| pid == own_pid))
+}
+
+/// The signals a daemon running in the foreground passes on to its command.
+fn forwarded_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            libc::sigaddset(&mut set, signal);
+        }
+        set
+    }
+}
+
+/// Waits for the signals in `forwarded_signals` and sends each on to the command,
+/// for a supervisor stopping a daemon that runs in the foreground. The command
+/// is not restarted after that.
+fn forward_signals(paths: &Mutex<DaemonPaths>, stopping: &AtomicBool) {
+    let set = forwarded_signals();
+    loop {
+        let mut signal = 0;
+        if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+            return;
+        }
+        stopping.store(true, Ordering::SeqCst);
+        let pid = std::fs::read_to_string(&paths.lock().unwrap().pid)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<i32>().ok());
+        if let Some(pid) = pid {
+            log::debug!("Forwarding signal {} to PID {}", signal, pid);
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+}
+
+/// Tells event listeners that the command of daemon `name` has exited for good.
+fn publish_exit(dir: &Path, name: &str, exit: &ExitInfo, failed: bool) {
+    let details = serde_json::json!({ "code": exit.code, "signal": exit.signal, "failed": failed });
+    events::publish(dir, name, "exited", details);
+}
+
+/// Sends SIGTERM to whatever command the daemon is running from inside the
+/// daemon, and SIGKILL if it has not exited after `grace`.
+fn stop_current_command(paths: &Mutex<DaemonPaths>, grace: Duration) {
+    let current_pid = || {
+        std::fs::read_to_string(&paths.lock().unwrap().pid)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<i32>().ok())
+    };
+    let Some(pid) = current_pid() else {
+        return;
+    };
+    log::debug!("Sending SIGTERM to PID {}", pid);
+    terminate(pid);
+    let deadline = deadline_after(grace);
+    while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        if !is_process_alive(pid) || paths.lock().unwrap().exit.exists() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    if current_pid() == Some(pid) {
+        log::debug!("PID {} outlived its grace period; sending SIGKILL", pid);
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+}
+
+/// Does from inside the daemon what `kill` does from outside once `--idle-timeout`
+/// has passed. The files go first: without a PID file the daemon leaves quietly
+/// when the command exits, instead of recording how it ended.
+fn shut_down_idle(dir: &Path, paths: &DaemonPaths) {
+    log::debug!("Daemon '{}' idle; shutting down", paths.name);
+    let pid = std::fs::read_to_string(&paths.pid)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<i32>().ok());
+    remove_daemon_files(paths).ok();
+    events::publish(dir, &paths.name, "killed", serde_json::json!({ "reason": "idle" }));
+    let Some(pid) = pid else {
+        return;
+    };
+    terminate(pid);
+    let deadline = Instant::now() + DEFAULT_KILL_TIMEOUT;
+    while is_process_alive(pid) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    if is_process_alive(pid) {
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+}
+
+/// How long the `--on-exit` hook may run before it is killed.
+const EXIT_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs the `--on-exit` hook, if there is one, through `sh -c` with the exit
+/// status in `ATTYVO_EXIT_CODE`, recording its output in the merged log.
+fn run_exit_hook(meta: &DaemonMeta, exit: &ExitInfo, merged_log: &Mutex<MergedLog>) {
+    let Some(hook) = &meta.on_exit else {
+        return;
+    };
+    let note = |message: &str| {
+        merged_log.lock().unwrap().note(message).ok();
+    };
+    let mut env = vec![("ATTYVO_EXIT_CODE", exit.exit_code().to_string())];
+    if let Some(signal) = exit.signal {
+        env.push(("ATTYVO_EXIT_SIGNAL", signal.to_string()));
+    }
+    let outcome = match run_shell(hook, meta, &env, EXIT_HOOK_TIMEOUT) {
+        Ok(outcome) => outcome,
+        Err(e) => return note(&format!("on-exit hook failed to start: {}", e)),
+    };
+    for line in String::from_utf8_lossy(&outcome.output).lines() {
+        note(&format!("on-exit: {}", line));
+    }
+    match outcome.status {
+        Some(status) => note(&format!("on-exit hook finished: {}", status)),
+        None => note(&format!(
+            "on-exit hook killed after {}s",
+            EXIT_HOOK_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+/// The result of `run_shell`: how the command ended, or `None` if it timed out,
+/// and everything it printed to stdout and stderr.
+struct ShellOutcome {
+    status: Option<std::process::ExitStatus>,
+    output: Vec<u8>,
+}
+
+/// Runs `script` through `sh -c` in the daemon's environment and working
+/// directory, killing it if it is still running after `timeout`.
+fn run_shell(
+    script: &str,
+    meta: &DaemonMeta,
+    env: &[(&str, String)],
+    timeout: Duration,
+) -> std::io::Result<ShellOutcome> {
+    let file_env = meta.file_env().map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+    let mut command = std::process::Command::new("sh");
+    command
+        .args(["-c", script])
+        .envs(file_env)
+        .envs(&meta.env)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    if let Some(cwd) = &meta.cwd {
+        command.current_dir(cwd);
+    }
+    let signals = forwarded_signals();
+    // A daemon in the foreground blocks the signals it forwards, which children inherit.
+    unsafe {
+        command.pre_exec(move || {
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &signals, std::ptr::null_mut());
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    let readers: Vec<_> = [
+        child.stdout.take().map(|out| Box::new(out) as Box<dyn Read + Send>),
+        child.stderr.take().map(|err| Box::new(err) as Box<dyn Read + Send>),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|mut pipe| {
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            pipe.read_to_end(&mut output).ok();
+            output
+        })
+    })
+    .collect();
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            _ => {
+                child.kill().ok();
+                child.wait().ok();
+                break None;
+            }
+        }
+    };
+    let mut output = Vec::new();
+    for reader in readers {
+        output.extend(reader.join().unwrap_or_default());
+    }
+    Ok(ShellOutcome { status, output })
+}
+
+/// The latest result of the `--health-cmd` check, written by the daemon.
+#[derive(Clone, Serialize, Deserialize)]
+struct HealthInfo {
+    healthy: bool,
+    /// Number of failed checks in a row.
+    failures: u32,
+    /// Unix time of the last check.
+    checked_at: u64,
+}
+
+/// Runs the health check every `health_interval` for as long as the daemon lives.
+/// After `health_retries` failures in a row a supervised command is terminated
+/// so that its restart policy can bring it back.
+fn check_health(
+    meta: &DaemonMeta,
+    paths: &Mutex<DaemonPaths>,
+    merged_log: &Mutex<MergedLog>,
+    stopping: &AtomicBool,
+) {
+    let Some(script) = &meta.health_cmd else {
+        return;
+    };
+    let interval = Duration::from_secs(meta.health_interval.max(1));
+    let mut health: Option<HealthInfo> = None;
+    loop {
+        std::thread::sleep(interval);
+        if stopping.load(Ordering::SeqCst) {
+            return;
+        }
+        let healthy = run_shell(script, meta, &[], interval)
+            .is_ok_and(|outcome| outcome.status.is_some_and(|status| status.success()));
+        let was_healthy = health.as_ref().is_none_or(|health| health.healthy);
+        let failures = match (healthy, &health) {
+            (true, _) => 0,
+            (false, Some(health)) => health.failures + 1,
+            (false, None) => 1,
+        };
+        if healthy != was_healthy {
+            let state = if healthy { "healthy" } else { "unhealthy" };
+            merged_log.lock().unwrap().note(&format!("Health check: {}", state)).ok();
+        }
+        let mut info = HealthInfo {
+            healthy,
+            failures,
+            checked_at: unix_now(),
+        };
+        if failures >= meta.health_retries && meta.restart != RestartPolicy::No {
+            let pid = std::fs::read_to_string(&paths.lock().unwrap().pid)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<i32>().ok());
+            if let Some(pid) = pid {
+                merged_log
+                    .lock()
+                    .unwrap()
+                    .note(&format!("Health check failed {} times; terminating the command", failures))
+                    .ok();
+                terminate(pid);
+                info.failures = 0;
+            }
+        }
+        if let Ok(json) = serde_json::to_string(&info) {
+            write_atomic(&paths.lock().unwrap().health, json.as_bytes()).ok();
+        }
+        health = Some(info);
+    }
+}
+
+/// Where a run of the command sends its output, shared by every restart.
+struct Outputs {
+    stdout_buffer: Arc<RingBuffer>,
+    stderr_buffer: Arc<RingBuffer>,
+    stdout_log: Arc<Mutex<RotatingLog>>,
+    stderr_log: Arc<Mutex<RotatingLog>>,
+    merged_log: Arc<Mutex<MergedLog>>,
+    /// Whether the command's output is also copied to our own stdout and stderr.
+    mirror: bool,
+}
+
+/// Spawns the command on `pts`, or on plain pipes without one, relays its output
+/// until it exits and returns how it exited. `started` is called with the
+/// command's PID once the PID file points at it.
+fn run_command(
+    meta: &mut DaemonMeta,
+    paths: &Mutex<DaemonPaths>,
+    input: &PtyInput,
+    pts: Option<pty_process::blocking::Pts>,
+    outputs: &Outputs,
+    started: impl FnOnce(u32),
+) -> Result<std::process::ExitStatus> {
+    let mut command = std::process::Command::new(meta.program());
+    if meta.shell.is_some() {
+        command.arg("-c").arg(meta.command_line());
+    } else {
+        command.args(&meta.args);
+    }
+    if meta.env_clear {
+        command.env_clear();
+    }
+    if let Some(cwd) = &meta.cwd {
+        command.current_dir(cwd);
+    }
+    // The standard library also drops supplementary groups when switching user as root.
+    if let Some(gid) = meta.gid {
+        command.gid(gid);
+    }
+    if let Some(uid) = meta.uid {
+        command.uid(uid);
+    }
+    // stdin and stdout are attached to the PTY so the command sees a real terminal,
+    // or with --no-pty to plain pipes; the threads below shuttle bytes between them
+    // and the FIFOs. stderr goes through a plain pipe so it can be logged alongside stdout.
+    let terminal = match &pts {
+        Some(pts) => {
+            if let Some(term) = &meta.term {
+                command.env("TERM", term);
+            }
+            let pty = input.pty().expect("a PTY is open for the command");
+            if meta.stdin_mode != StdinMode::Line {
+                set_stdin_mode(&pty, meta.stdin_mode)?;
+            }
+            command
+                .stdin(pts.as_fd().try_clone_to_owned()?)
+                .stdout(pts.as_fd().try_clone_to_owned()?);
+            Some(pts.as_raw_fd())
+        }
+        None => {
+            command
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped());
+            None
+        }
+    };
+    let limits = resource_limits(meta);
+    let signals = forwarded_signals();
+    // Done in the child between fork and exec, so the daemon itself stays unlimited.
+    // A daemon in the foreground blocks the signals it forwards, which children inherit.
+    unsafe {
+        command.pre_exec(move || {
+            // The command leads a session and process group of its own, so that
+            // signals sent to the group reach everything it started. On a PTY,
+            // that session's controlling terminal is the PTY.
+            if libc::setsid() < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if let Some(pts) = terminal
+                && libc::ioctl(pts, libc::TIOCSCTTY, 0) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &signals, std::ptr::null_mut());
+            for &(resource, value) in &limits {
+                let limit = libc::rlimit {
+                    rlim_cur: value as libc::rlim_t,
+                    rlim_max: value as libc::rlim_t,
+                };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        })
+    };
+    let mut child = command
+        .envs(meta.file_env()?)
+        .envs(&meta.env)
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    // Our copies of the PTY's child end would keep its output from ever ending.
+    drop((command, pts));
+    let child_stderr = child.stderr.take().expect("stderr is piped");
+    let output: Box<dyn Read + Send> = match (child.stdin.take(), child.stdout.take()) {
+        (Some(stdin), Some(stdout)) => {
+            input.connect_pipe(File::from(OwnedFd::from(stdin)), child.id() as libc::pid_t);
+            Box::new(stdout)
+        }
+        _ => Box::new(PtyReader(input.pty().expect("a PTY is open for the command"))),
+    };
+    // The PID file points at the command itself so that signals reach it directly.
+    // Its start time goes into the metadata first, so anyone who finds the new PID
+    // can also tell whether the process behind it is still the command.
+    meta.pid_start_time = procfs::start_time(child.id() as i32);
+    meta.paused = false;
+    {
+        let paths = paths.lock().unwrap();
+        save_meta(&paths, meta)?;
+        write_atomic(&paths.pid, child.id().to_string().as_bytes())?;
+    }
+    log::debug!("Started {} as PID {}", meta.command_line(), child.id());
+    started(child.id());
+
+    let (output_done, output_finished) = mpsc::channel();
+    {
+        let buffer = Arc::clone(&outputs.stdout_buffer);
+        let (stdout_log, merged_log) = (Arc::clone(&outputs.stdout_log), Arc::clone(&outputs.merged_log));
+        let (output_done, mirror) = (output_done.clone(), outputs.mirror);
+        std::thread::spawn(move || {
+            relay_output(output, &buffer, |output| {
+                stdout_log.lock().unwrap().write(output).ok();
+                merged_log.lock().unwrap().write(Stream::Out, output).ok();
+                if mirror {
+                    let mut stdout = std::io::stdout();
+                    stdout.write_all(output).and_then(|()| stdout.flush()).ok();
+                }
+            });
+            output_done.send(()).ok();
+        });
+    }
+    {
+        let buffer = Arc::clone(&outputs.stderr_buffer);
+        let (stderr_log, merged_log) = (Arc::clone(&outputs.stderr_log), Arc::clone(&outputs.merged_log));
+        let mirror = outputs.mirror;
+        std::thread::spawn(move || {
+            relay_output(child_stderr, &buffer, |output| {
+                stderr_log.lock().unwrap().write(output).ok();
+                merged_log.lock().unwrap().write(Stream::Err, output).ok();
+                if mirror {
+                    std::io::stderr().write_all(output).ok();
+                }
+            });
+            output_done.send(()).ok();
+        });
+    }
+    // A command that dies straight away, e.g. on bad arguments, is alive for a
+    // moment all the same; only one that is still there after that has started.
+    if !meta.started_ok {
+        let deadline = Instant::now() + START_CONFIRM_DELAY;
+        while child.try_wait()?.is_none() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if child.try_wait()?.is_none() {
+            meta.started_ok = true;
+            save_meta(&paths.lock().unwrap(), meta)?;
+        }
+    }
+    let status = child.wait()?;
+    // Give the relays a chance to copy whatever the command printed last, without
+    // hanging forever if one of its own children still holds the terminal open.
+    let deadline = Instant::now() + Duration::from_secs(1);
+    for _ in 0..2 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        output_finished.recv_timeout(remaining).ok();
+    }
+    outputs.merged_log.lock().unwrap().finish().ok();
+    Ok(status)
+}
+
+/// How long a freshly spawned command has to stay alive to count as started.
+const START_CONFIRM_DELAY: Duration = Duration::from_millis(200);
+
+/// How long to wait before the `attempt`th restart: `initial` seconds, doubling
+/// each time, but never more than 30s unless `initial` itself is longer.
+fn restart_delay(initial: u64, attempt: u32) -> Duration {
+    let delay = initial.saturating_mul(1 << (attempt - 1).min(16));
+    Duration::from_secs(delay.min(initial.max(30)))
+}
+
+/// Writes `contents` to `path` via a rename, so readers never see a partial file.
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// The daemon's end of the command's input. Bytes from the stdin pipe are
+/// forwarded into the PTY one chunk at a time under a lock, so that control
+/// messages such as `eof` are applied after everything written before them.
+struct PtyInput {
+    /// Where the current run of the command reads its input; replaced when it restarts.
+    terminal: Mutex<Terminal>,
+    /// The size most recently requested, so a restarted command gets it too.
+    size: Mutex<(u16, u16)>,
+    stdin: File,
+    /// Whether the last byte forwarded was a newline, or nothing was forwarded yet.
+    at_line_start: Mutex<bool>,
+    /// When input last arrived, or the daemon started if none has.
+    last_input: Mutex<Instant>,
+}
+
+/// What the command reads its input from.
+#[derive(Clone)]
+enum Terminal {
+    /// The PTY the command runs on.
+    Pty(Arc<pty_process::blocking::Pty>),
+    /// With `--no-pty`, a plain pipe into the command and the command's PID,
+    /// which leads its process group; `None` before the command is spawned and
+    /// after `eof` closed the pipe.
+    Pipe(Option<(Arc<File>, libc::pid_t)>),
+}
+
+impl PtyInput {
+    fn terminal(&self) -> Terminal {
+        self.terminal.lock().unwrap().clone()
+    }
+
+    fn pty(&self) -> Option<Arc<pty_process::blocking::Pty>> {
+        match self.terminal() {
+            Terminal::Pty(pty) => Some(pty),
+            Terminal::Pipe(_) => None,
+        }
+    }
+
+    /// Switches input over to the PTY of a new run of the command.
+    fn replace_pty(&self, pty: pty_process::blocking::Pty) {
+        let (rows, cols) = *self.size.lock().unwrap();
+        pty.resize(pty_process::Size::new(rows, cols)).ok();
+        *self.terminal.lock().unwrap() = Terminal::Pty(Arc::new(pty));
+        *self.at_line_start.lock().unwrap() = true;
+    }
+
+    /// Switches input over to the stdin pipe of a new run of a `--no-pty` command.
+    fn connect_pipe(&self, stdin: File, pid: libc::pid_t) {
+        *self.terminal.lock().unwrap() = Terminal::Pipe(Some((Arc::new(stdin), pid)));
+        *self.at_line_start.lock().unwrap() = true;
+    }
+
+    fn resize(&self, rows: u16, cols: u16) {
+        *self.size.lock().unwrap() = (rows, cols);
+        if let Some(pty) = self.pty() {
+            pty.resize(pty_process::Size::new(rows, cols)).ok();
+        }
+    }
+
+    /// Hands `bytes` to the command. Writing happens outside the lock, so a
+    /// command that stops reading cannot hold up a restart.
+    fn write(&self, bytes: &[u8]) {
+        match self.terminal() {
+            Terminal::Pty(pty) => (&*pty).write_all(bytes).ok(),
+            Terminal::Pipe(Some((stdin, _))) => (&*stdin).write_all(bytes).ok(),
+            Terminal::Pipe(None) => None,
+        };
+    }
+
+    /// Copies everything written to the stdin pipe into the PTY.
+    fn relay(&self) {
+        let mut buf = [0u8; 4096];
+        loop {
+            if !wait_readable(&self.stdin) {
+                break;
+            }
+            let mut at_line_start = self.at_line_start.lock().unwrap();
+            let n = match (&self.stdin).read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            // Input that arrives while the command is down between restarts is dropped.
+            self.write(&buf[..n]);
+            *at_line_start = buf[n - 1] == b'\n';
+            *self.last_input.lock().unwrap() = Instant::now();
+        }
+    }
+
+    /// Makes the command's next read of its terminal return end-of-file. On a PTY
+    /// that means typing the EOF character, twice if a partial line is pending;
+    /// a plain pipe is closed instead, which ends the input of that run for good.
+    fn send_eof(&self) {
+        loop {
+            let mut at_line_start = self.at_line_start.lock().unwrap();
+            if pending_bytes(&self.stdin) == 0 {
+                match self.pty() {
+                    Some(pty) => {
+                        let eof = eof_char(&pty);
+                        let presses = if *at_line_start { 1 } else { 2 };
+                        for _ in 0..presses {
+                            (&*pty).write_all(&[eof]).ok();
+                        }
+                    }
+                    None => *self.terminal.lock().unwrap() = Terminal::Pipe(None),
+                }
+                *at_line_start = true;
+                *self.last_input.lock().unwrap() = Instant::now();
+                return;
+            }
+            drop(at_line_start);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Types the interrupt character once everything written before it has been
+    /// delivered, so the line discipline sends SIGINT to the foreground process.
+    /// Without a PTY, SIGINT goes to the command's process group directly.
+    fn send_interrupt(&self) {
+        loop {
+            let mut at_line_start = self.at_line_start.lock().unwrap();
+            if pending_bytes(&self.stdin) == 0 {
+                match self.terminal() {
+                    Terminal::Pty(pty) => {
+                        (&*pty).write_all(&[intr_char(&pty)]).ok();
+                    }
+                    Terminal::Pipe(Some((_, pid))) => unsafe {
+                        libc::killpg(pid, libc::SIGINT);
+                    },
+                    Terminal::Pipe(None) => {}
+                }
+                // The interrupted line is thrown away.
+                *at_line_start = true;
+                *self.last_input.lock().unwrap() = Instant::now();
+                return;
+            }
+            drop(at_line_start);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Blocks until `file` has data to read, returning `false` on error.
+fn wait_readable(file: &File) -> bool {
+    let mut fds = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        if unsafe { libc::poll(&mut fds, 1, -1) } >= 0 {
+            return fds.revents & libc::POLLIN != 0;
+        }
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return false;
+        }
+    }
+}
+
+/// Number of bytes sitting unread in a pipe.
+fn pending_bytes(file: &File) -> usize {
+    let mut pending: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FIONREAD, &mut pending) } != 0 {
+        return 0;
+    }
+    pending as usize
+}
+
+/// The character the PTY's line discipline treats as end-of-file, usually ^D.
+fn eof_char(pty: &pty_process::blocking::Pty) -> u8 {
+    control_char(pty, libc::VEOF, 0x04)
+}
+
+/// The character the PTY's line discipline turns into SIGINT, usually ^C.
+fn intr_char(pty: &pty_process::blocking::Pty) -> u8 {
+    control_char(pty, libc::VINTR, 0x03)
+}
+
+/// The special character in slot `index` of the PTY's termios, or `default`
+/// if it cannot be read.
+fn control_char(pty: &pty_process::blocking::Pty, index: usize, default: u8) -> u8 {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(pty.as_raw_fd(), &mut termios) } != 0 {
+        return default;
+    }
+    termios.c_cc[index]
+}
+
+/// Switches the PTY's line discipline to `mode` for the next command to run on it.
+fn set_stdin_mode(pty: &pty_process::blocking::Pty, mode: StdinMode) -> Result<()> {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(pty.as_raw_fd(), &mut termios) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    match mode {
+        StdinMode::Line => termios.c_lflag |= libc::ICANON,
+        StdinMode::Char => {
+            termios.c_lflag &= !libc::ICANON;
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+        }
+    }
+    if unsafe { libc::tcsetattr(pty.as_raw_fd(), libc::TCSANOW, &termios) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Reads the command's output from its PTY, which the input side writes to meanwhile.
+struct PtyReader(Arc<pty_process::blocking::Pty>);
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (&*self.0).read(buf)
+    }
+}
+
+/// Copies the command's output into its log and into the stdout pipe, until the
+/// command closes its end.
+fn relay_output(mut source: impl Read, buffer: &RingBuffer, mut log: impl FnMut(&[u8])) {
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        log(&buf[..n]);
+        // The buffer never blocks: when nobody is draining the pipe, the oldest
+        // output is dropped rather than stalling the command. The log keeps it all.
+        buffer.push(&buf[..n]);
+    }
+}
+
+fn handle_control(control: File, input: &PtyInput, stopping: &AtomicBool, on_rename: impl Fn(&str)) {
+    for line in BufReader::new(control).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["resize", rows, cols] => {
+                if let (Ok(rows), Ok(cols)) = (rows.parse(), cols.parse()) {
+                    input.resize(rows, cols);
+                }
+            }
+            ["eof"] => input.send_eof(),
+            ["interrupt"] => input.send_interrupt(),
+            // Sent before the command is terminated on purpose, so it is not restarted.
+            ["stop"] => stopping.store(true, Ordering::SeqCst),
+            ["rename", new_name] if validate_name(new_name).is_ok() => on_rename(new_name),
+            _ => {}
+        }
+    }
+}
+
+const DEFAULT_PIPE_DIR: &str = "/tmp/daemon_pipes";
+
+/// `TERM` given to commands unless their environment sets one.
+const DEFAULT_TERM: &str = "xterm-256color";
+
+/// The `TERM` a command with extra environment `env` ends up with.
+fn effective_term(env: &BTreeMap<String, String>) -> String {
+    env.get("TERM").map_or(DEFAULT_TERM, String::as_str).to_string()
+}
+
+/// Creates the daemon's FIFOs with permissions `mode`, as reduced by the umask.
+fn create_files(dir: &Path, paths: &DaemonPaths, mode: u32) -> Result<()> {
+    ensure_pipe_dir_exists(dir)?;
+
+    for path in [&paths.stdin, &paths.stdout, &paths.stderr, &paths.control] {
+        log::debug!("Creating FIFO {} with mode {:o}", path.display(), mode);
+        interprocess::os::unix::fifo_file::create_fifo(path, mode).map_err(|e| {
+            // mkfifo reports a filesystem without FIFO support in several ways,
+            // none of which say as much.
+            match e.raw_os_error() {
+                Some(libc::EPERM | libc::EOPNOTSUPP | libc::ENOSYS | libc::EINVAL) => anyhow::anyhow!(
+                    "Cannot create named pipes in {} ({}); its filesystem probably does not \
+                     support FIFOs. Point --dir or ATTYVO_DIR at a directory on a local \
+                     filesystem instead, e.g. --dir /tmp/attyvo-$USER",
+                    dir.display(),
+                    e
+                ),
+                _ => e.into(),
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+fn get_files(paths: &DaemonPaths) -> Result<DaemonFiles> {
+    let stdin = File::options().read(true).write(true).open(&paths.stdin)?;
+    let stdout = File::options()
+        .read(true)
+        .append(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&paths.stdout)?;
+    let stderr = File::options()
+        .read(true)
+        .append(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&paths.stderr)?;
+    let control = File::options().read(true).write(true).open(&paths.control)?;
+    Ok(DaemonFiles {
+        stdin,
+        stdout,
+        stderr,
+        control,
+    })
+}
+
+/// Creates the pipe directory if needed and makes sure it is safe to put pipes
+/// in: a real directory, not a symlink, owned by us and not writable by others.
+/// Otherwise anyone who got to a shared path like /tmp first could redirect or
+/// intercept every daemon's I/O.
+fn ensure_pipe_dir_exists(dir: &Path) -> Result<()> {
+    if let Some(parent) = dir.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e.into()),
+    }
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if metadata.file_type().is_symlink() {
+        return Err(anyhow::anyhow!(
+            "Pipe directory {} is a symlink; refusing to use it",
+            dir.display()
+        ));
+    }
+    if !metadata.is_dir() {
+        return Err(anyhow::anyhow!(
+            "Pipe directory {} is not a directory",
+            dir.display()
+        ));
+    }
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid {
+        return Err(anyhow::anyhow!(
+            "Pipe directory {} is owned by uid {}, not by us (uid {}); refusing to use it",
+            dir.display(),
+            metadata.uid(),
+            uid
+        ));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(anyhow::anyhow!(
+            "Pipe directory {} is writable by other users (mode {:o}); run `chmod go-w {}` to use it",
+            dir.display(),
+            metadata.mode() & 0o777,
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the `(rows, cols)` of the terminal attached to stdout, if there is one.
+fn terminal_size() -> Option<(u16, u16)> {
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut winsize) } != 0 {
+        return None;
+    }
+    if winsize.ws_row == 0 || winsize.ws_col == 0 {
+        return None;
+    }
+    Some((winsize.ws_row, winsize.ws_col))
+}
+
+/// Creates the daemon's files and turns into the daemon. With `ready_timeout`,
+/// the daemon is started from a forked child instead and this returns once it
+/// is up and running.
+fn create(dir: &Path, name: &str, meta: &DaemonMeta, ready_timeout: Option<Duration>) -> Result<()> {
+    log::debug!("Creating daemon '{}' in {}: {}", name, dir.display(), meta.command_line());
+    if let Some(timeout) = ready_timeout {
+        let (child, files) = spawn_daemon(dir, name, meta)?;
+        drop(files);
+        return wait_until_ready(dir, name, child, timeout);
+    }
+    let (files, lock) = prepare_daemon(dir, name, meta)?;
+    start_daemon(dir, name, meta, files, lock, false)?;
+    Ok(())
+}
+
+/// Does what `create` does, except that the daemon runs in this process until
+/// its command exits for good, for supervisors such as systemd that want to own
+/// the process. The command's output is also copied to our stdout and stderr,
+/// and SIGTERM, SIGINT and SIGHUP are passed on to it. Returns the exit code
+/// the command ended with.
+fn run_foreground(dir: &Path, name: &str, meta: &DaemonMeta) -> Result<i32> {
+    log::debug!(
+        "Running daemon '{}' in {} in the foreground: {}",
+        name,
+        dir.display(),
+        meta.command_line()
+    );
+    let (files, lock) = prepare_daemon(dir, name, meta)?;
+    Ok(start_daemon(dir, name, meta, files, lock, true)?.exit_code())
+}
+
+/// Starts the daemon from a forked child and returns the child's PID, which
+/// exits once the daemon has detached. The returned handles keep the daemon's
+/// pipes from losing buffered output until the caller has opened its own.
+fn spawn_daemon(dir: &Path, name: &str, meta: &DaemonMeta) -> Result<(libc::pid_t, DaemonFiles)> {
+    let (files, lock) = prepare_daemon(dir, name, meta)?;
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        // The child becomes the daemon and must never return into the caller,
+        // which may go on to create more daemons.
+        0 => std::process::exit(match start_daemon(dir, name, meta, files, lock, false) {
+            Ok(_) => 0,
+            Err(_) => 1,
+        }),
+        child => {
+            drop(lock);
+            Ok((child, files))
+        }
+    }
+}
+
+/// The `--limit-*` options in `meta` that were given, as rlimit resources and values.
+fn resource_limits(meta: &DaemonMeta) -> Vec<(RlimitResource, u64)> {
+    [
+        (libc::RLIMIT_AS, meta.limit_as),
+        (libc::RLIMIT_CPU, meta.limit_cpu),
+        (libc::RLIMIT_NOFILE, meta.limit_nofile),
+    ]
+    .into_iter()
+    .filter_map(|(resource, value)| Some((resource, value?)))
+    .collect()
+}
+
+/// The type libc takes rlimit resources as, which differs between platforms.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type RlimitResource = libc::c_int;
+
+/// Makes sure none of `meta`'s limits is above the hard limit attyvo runs under,
+/// which only a privileged process may raise.
+fn check_limits(meta: &DaemonMeta) -> Result<()> {
+    if unsafe { libc::geteuid() } == 0 {
+        return Ok(());
+    }
+    for (resource, value) in resource_limits(meta) {
+        let mut current = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(resource, &mut current) } == 0
+            && current.rlim_max != libc::RLIM_INFINITY
+            && value as libc::rlim_t > current.rlim_max
+        {
+            let option = match resource {
+                libc::RLIMIT_AS => "--limit-as",
+                libc::RLIMIT_CPU => "--limit-cpu",
+                _ => "--limit-nofile",
+            };
+            return Err(anyhow::anyhow!(
+                "{} {} is above the hard limit of {} that attyvo runs under",
+                option,
+                value,
+                current.rlim_max
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks no daemon of that name is running and sets up the new one's files,
+/// holding its create lock.
+fn prepare_daemon(dir: &Path, name: &str, meta: &DaemonMeta) -> Result<(DaemonFiles, CreateLock)> {
+    validate_name(name).map_err(anyhow::Error::msg)?;
+    // Once detached there is nobody left to tell about a typo in the command.
+    check_command(meta)?;
+    check_limits(meta)?;
+    let paths = DaemonPaths::new(dir, name);
+    ensure_pipe_dir_exists(dir)?;
+    let lock = CreateLock::acquire(&paths, name)?;
+    if ensure_process_is_running(dir, name).is_ok() {
+        return Err(Failure::AlreadyExists(name.to_string()).into());
+    }
+    // Whatever is left over belongs to a dead daemon and would clash with the new pipes.
+    remove_runtime_files(&paths)?;
+    create_files(dir, &paths, meta.fifo_mode)?;
+    let files = get_files(&paths)?;
+    save_meta(&paths, meta)?;
+    Ok((files, lock))
+}
+
+/// Makes sure `meta`'s command, or the shell that runs it, can be executed,
+/// looking it up on the `PATH` it will run with unless it names a file directly.
+fn check_command(meta: &DaemonMeta) -> Result<()> {
+    let executable = |path: &Path| {
+        std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.mode() & 0o111 != 0)
+    };
+    let program = meta.program();
+    let command = Path::new(program);
+    if program.contains('/') {
+        let path = match &meta.cwd {
+            Some(cwd) => cwd.join(command),
+            None => command.to_path_buf(),
+        };
+        if !path.exists() {
+            return Err(Failure::CommandNotFound(program.to_string()).into());
+        }
+        if !executable(&path) {
+            return Err(Failure::NotExecutable(program.to_string()).into());
+        }
+        return Ok(());
+    }
+    let file_env = meta.file_env()?;
+    let file_path = file_env.iter().rev().find(|(key, _)| key == "PATH").map(|(_, path)| path);
+    let search_path = match meta.env.get("PATH").or(file_path) {
+        Some(path) => Some(path.into()),
+        None if meta.env_clear => None,
+        None => std::env::var_os("PATH"),
+    };
+    // Without a PATH the command is looked up in the system's default places;
+    // leave it to the spawn to find out.
+    let Some(search_path) = search_path else {
+        return Ok(());
+    };
+    if std::env::split_paths(&search_path).any(|dir| executable(&dir.join(command))) {
+        return Ok(());
+    }
+    Err(Failure::CommandNotFound(program.to_string()).into())
+}
+
+/// An exclusive `flock` on a daemon's lock file. `create` holds it until the
+/// command's PID file is written, so a second create of the same name fails
+/// instead of replacing the pipes of a daemon that is still starting.
+struct CreateLock {
+    _file: File,
+}
+
+impl CreateLock {
+    fn acquire(paths: &DaemonPaths, name: &str) -> Result<CreateLock> {
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&paths.lock)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::WouldBlock {
+                return Err(anyhow::anyhow!(
+                    "Create of daemon '{}' already in progress",
+                    name
+                ));
+            }
+            return Err(error.into());
+        }
+        Ok(CreateLock { _file: file })
+    }
+}
+
+/// Waits for the forked `child` to daemonize and for the daemon to confirm that
+/// its command survived being started, failing if it exits first or `timeout`
+/// passes.
+fn wait_until_ready(dir: &Path, name: &str, child: libc::pid_t, timeout: Duration) -> Result<()> {
+    // The child exits as soon as it has detached, or earlier if that failed.
+    let mut status = 0;
+    unsafe { libc::waitpid(child, &mut status, 0) };
+    if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+        return Err(anyhow::anyhow!("Daemon '{}' failed to start", name));
+    }
+    let paths = DaemonPaths::new(dir, name);
+    let deadline = deadline_after(timeout);
+    loop {
+        if let Ok(exit) = load_exit(&paths) {
+            return Err(anyhow::anyhow!("Daemon '{}' {} during startup", name, exit));
+        }
+        let confirmed = load_meta(&paths).is_ok_and(|meta| meta.started_ok);
+        if confirmed && ensure_process_is_running(dir, name).is_ok() {
+            return Ok(());
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(anyhow::anyhow!(
+                "Daemon '{}' did not become ready within {}s",
+                name,
+                timeout.as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Formats a number of seconds compactly, e.g. `3h12m` or `45s`.
+fn format_duration(secs: u64) -> String {
+    let (days, hours, minutes, seconds) =
+        (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d{}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h{}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m{}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Formats milliseconds since the epoch as a UTC timestamp, e.g. `2024-05-01T12:30:00.250Z`.
+fn format_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let days = (secs / 86400) as i64;
+    // Civil-from-days conversion for the proleptic Gregorian calendar.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        millis % 1000
+    )
+}
+
+fn save_meta(paths: &DaemonPaths, meta: &DaemonMeta) -> Result<()> {
+    std::fs::write(&paths.meta, serde_json::to_string_pretty(meta)?)?;
+    Ok(())
+}
+
+fn load_meta(paths: &DaemonPaths) -> Result<DaemonMeta> {
+    let contents = std::fs::read_to_string(&paths.meta)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+fn load_exit(paths: &DaemonPaths) -> Result<ExitInfo> {
+    let contents = std::fs::read_to_string(&paths.exit)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+fn ensure_pid_file(dir: &Path, name: &str) -> Result<()> {
+    let pid_file_path = DaemonPaths::new(dir, name).pid;
+    if !pid_file_path.exists() {
+        return Err(Failure::NotFound(name.to_string()).into());
+    }
+    Ok(())
+}
+
+/// How long writers wait for the daemon to open its end of a FIFO.
+const FIFO_OPEN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Which end of a FIFO `open_fifo_with_timeout` opens.
+#[derive(Clone, Copy)]
+enum FifoAccess {
+    /// A non-blocking reader, which may find the pipe empty.
+    Read,
+    /// A blocking writer, whose writes wait for room in the pipe as usual.
+    Write,
+}
+
+/// Opens daemon `name`'s FIFO at `path` without ever blocking in `open`, which
+/// for a FIFO waits until somebody opens the other end, possibly forever.
+/// Readers get the pipe straight away. Writers wait up to `timeout` for the
+/// daemon to be reading, after which it counts as not responding.
+fn open_fifo_with_timeout(
+    name: &str,
+    path: &Path,
+    access: FifoAccess,
+    timeout: Duration,
+) -> Result<File> {
+    let mut options = File::options();
+    options.custom_flags(libc::O_NONBLOCK);
+    match access {
+        FifoAccess::Read => options.read(true),
+        FifoAccess::Write => options.write(true),
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        match options.open(path) {
+            Ok(file) => {
+                if let FifoAccess::Write = access {
+                    unsafe {
+                        let flags = libc::fcntl(file.as_raw_fd(), libc::F_GETFL);
+                        libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+                    }
+                }
+                return Ok(file);
+            }
+            // ENXIO means nobody has the FIFO open for reading yet.
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    log::debug!("Nobody opened {} for reading within {:?}", path.display(), timeout);
+                    return Err(Failure::NotResponding(name.to_string()).into());
+                }
+                // The daemon opening its end cannot be polled for; retry until the deadline.
+                std::thread::sleep(remaining.min(Duration::from_millis(20)));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn write(dir: &Path, name: &str, message: &[u8], newline: bool) -> Result<()> {
+    ensure_process_is_running(dir, name)?;
+    let stdin_path = DaemonPaths::new(dir, name).stdin;
+    let mut file = open_fifo_with_timeout(name, &stdin_path, FifoAccess::Write, FIFO_OPEN_TIMEOUT)?;
+    file.write_all(message)?;
+    if newline {
+        file.write_all(b"\n")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Streams the contents of `path` (or our own stdin for `-`) into the daemon's
+/// stdin, decoding them first if they are `base64`.
+fn write_file(dir: &Path, name: &str, path: &Path, base64: bool) -> Result<u64> {
+    ensure_process_is_running(dir, name)?;
+    let mut source: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    if base64 {
+        let mut encoded = String::new();
+        source.read_to_string(&mut encoded)?;
+        source = Box::new(std::io::Cursor::new(decode_base64(&encoded)?));
+    }
+    let stdin_path = DaemonPaths::new(dir, name).stdin;
+    let mut file = open_fifo_with_timeout(name, &stdin_path, FifoAccess::Write, FIFO_OPEN_TIMEOUT)?;
+    let written = std::io::copy(&mut source, &mut file)?;
+    file.flush()?;
+    Ok(written)
+}
+
+/// Expands backslash escapes (`\n`, `\r`, `\t`, `\e`, `\0`, `\\` and `\xNN`) into raw bytes.
+fn unescape(s: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('e') => bytes.push(0x1b),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid escape '\\x{}'", hex))?;
+                bytes.push(byte);
+            }
+            Some(other) => return Err(anyhow::anyhow!("Unknown escape '\\{}'", other)),
+            None => return Err(anyhow::anyhow!("Trailing backslash in message")),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decodes standard base64, with or without padding, ignoring whitespace.
+fn decode_base64(s: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+    let (mut acc, mut bits) = (0u32, 0);
+    let data = s.trim_end_matches(|c: char| c == '=' || c.is_ascii_whitespace());
+    for c in data.chars().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' => 62,
+            '/' => 63,
+            _ => return Err(anyhow::anyhow!("Invalid base64 character {:?}", c)),
+        };
+        acc = (acc << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    // A single leftover character cannot encode a whole byte.
+    if bits >= 6 {
+        return Err(anyhow::anyhow!("Truncated base64 input"));
+    }
+    Ok(bytes)
+}
+
+/// Sends a single-line message to the daemon's control pipe.
+fn send_control(dir: &Path, name: &str, message: &str) -> Result<()> {
+    ensure_process_is_running(dir, name)?;
+    let control_path = DaemonPaths::new(dir, name).control;
+    let mut file = open_fifo_with_timeout(name, &control_path, FifoAccess::Write, FIFO_OPEN_TIMEOUT)?;
+    writeln!(file, "{}", message)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn resize_daemon(dir: &Path, name: &str, rows: u16, cols: u16) -> Result<()> {
+    if load_meta(&DaemonPaths::new(dir, name)).is_ok_and(|meta| meta.no_pty) {
+        return Err(anyhow::anyhow!(
+            "Daemon '{}' runs without a PTY, so it has no terminal to resize",
+            name
+        ));
+    }
+    send_control(dir, name, &format!("resize {} {}", rows, cols))
+}
+
+/// Signals end-of-file on the daemon's stdin once all previously written input
+/// has been delivered.
+fn send_eof(dir: &Path, name: &str) -> Result<()> {
+    send_control(dir, name, "eof")
+}
+
+/// Interrupts the daemon's command as if Ctrl-C was pressed once all previously
+/// written input has been delivered.
+fn send_interrupt(dir: &Path, name: &str) -> Result<()> {
+    send_control(dir, name, "interrupt")
+}
+
+/// The reader that `read` continues for when it is not given a consumer ID.
+const DEFAULT_CONSUMER: &str = "default";
+
+/// Returns the daemon's stdout that `consumer` has not read yet, at most
+/// `max_bytes` of it, and whether more was left behind for the next read.
+fn read_stdout(
+    dir: &Path,
+    name: &str,
+    consumer: &str,
+    max_bytes: Option<u64>,
+) -> Result<(Vec<u8>, bool)> {
+    let mut output = Vec::new();
+    let truncated = read_as_consumer(dir, name, consumer, max_bytes, false, |chunk| {
+        output.extend_from_slice(chunk);
+        Ok(())
+    })?;
+    Ok((output, truncated))
+}
+
+/// Returns whatever is currently buffered in the FIFO at `path`, up to `max_bytes`,
+/// without waiting for more. The flag tells whether anything was left unread.
+fn drain_fifo(name: &str, path: &Path, max_bytes: Option<u64>) -> Result<(Vec<u8>, bool)> {
+    let fifo = open_fifo_with_timeout(name, path, FifoAccess::Read, FIFO_OPEN_TIMEOUT)?;
+    let mut output = Vec::new();
+    match (&fifo).take(max_bytes.unwrap_or(u64::MAX)).read_to_end(&mut output) {
+        Ok(_) => {}
+        // Nothing more is buffered right now; what was read so far is kept.
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+        Err(e) => return Err(e.into()),
+    }
+    let truncated = max_bytes.is_some_and(|max| output.len() as u64 >= max) && pending_bytes(&fifo) > 0;
+    Ok((output, truncated))
+}
+
+/// Blocks until the daemon has logged stdout that `consumer` has not read yet,
+/// returning false if `timeout` passes first. Also returns once the command stops.
+fn wait_for_stdout(
+    dir: &Path,
+    name: &str,
+    consumer: &str,
+    timeout: Option<Duration>,
+) -> Result<bool> {
+    ensure_process_is_running(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let cursor = load_cursor(&paths, consumer);
+    let deadline = timeout.and_then(deadline_after);
+    let changes = DirChanges::watch(dir)?;
+    loop {
+        let running = ensure_process_is_running(dir, name).is_ok();
+        let (output, _) = logfile::read_after(&paths.stdout_log, cursor, Some(1)).map_err(|e| {
+            anyhow::anyhow!("Cannot read stdout log of daemon '{}': {}", name, e)
+        })?;
+        if !output.is_empty() || !running {
+            return Ok(true);
+        }
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return Ok(false);
+        }
+        changes.wait(remaining)?;
+    }
+}
+
+/// Change notifications for the pipe directory. A daemon's logs, PID and exit
+/// files all live there, so whatever ends a wait on it shows up as a change.
+struct DirChanges {
+    _watcher: notify::RecommendedWatcher,
+    changes: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl DirChanges {
+    /// How long `wait` blocks at most, since a daemon killed outright changes nothing.
+    const RECHECK: Duration = Duration::from_secs(1);
+
+    fn watch(dir: &Path) -> Result<Self> {
+        let (tx, changes) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| anyhow::anyhow!("Cannot watch {}: {}", dir.display(), e))?;
+        Ok(DirChanges {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Blocks until something in the directory changes, or `timeout` passes.
+    /// Files being opened or read, as our own reads of the logs do, don't count.
+    fn wait(&self, timeout: Option<Duration>) -> Result<()> {
+        let deadline = Instant::now() + timeout.map_or(Self::RECHECK, |t| t.min(Self::RECHECK));
+        while let Ok(event) = self
+            .changes
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            if !matches!(event?.kind, EventKind::Access(_)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Waits for a line of the daemon's stdout, as kept in its log with ANSI
+/// sequences removed, to match `pattern`, and returns that line. Output logged
+/// before the call counts too. `None` means `timeout` passed first.
+fn wait_for_output(
+    dir: &Path,
+    name: &str,
+    pattern: &regex::Regex,
+    timeout: Option<Duration>,
+) -> Result<Option<String>> {
+    ensure_pid_file(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let deadline = timeout.and_then(deadline_after);
+    let mut stripper = ansi::AnsiStripper::default();
+    let (mut cursor, mut partial) = (None, String::new());
+    loop {
+        // Check first so that whatever was logged right before the exit is still seen.
+        let running = ensure_process_is_running(dir, name).is_ok();
+        let (output, next) = logfile::read_after(&paths.stdout_log, cursor, None).map_err(|e| {
+            anyhow::anyhow!("Cannot read stdout log of daemon '{}': {}", name, e)
+        })?;
+        cursor = Some(next);
+        partial.push_str(&String::from_utf8_lossy(&stripper.strip(&output)));
+        // The unfinished last line is checked too, as prompts often lack a newline.
+        if let Some(line) = partial
+            .split('\n')
+            .map(|line| line.trim_end_matches('\r'))
+            .find(|line| pattern.is_match(line))
+        {
+            return Ok(Some(line.to_string()));
+        }
+        if let Some(pos) = partial.rfind('\n') {
+            partial.drain(..=pos);
+        }
+        if !running {
+            return Err(anyhow::anyhow!(
+                "Daemon '{}' stopped before its output matched '{}'",
+                name,
+                pattern
+            ));
+        }
+        let mut pause = Duration::from_millis(200);
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            pause = pause.min(remaining);
+        }
+        std::thread::sleep(pause);
+    }
+}
+
+/// Converts an optional timeout into milliseconds for `poll`, where -1 means forever.
+fn poll_timeout_ms(timeout: Option<Duration>) -> i32 {
+    timeout.map_or(-1, |timeout| timeout.as_millis().min(i32::MAX as u128) as i32)
+}
+
+fn follow_fifo(
+    dir: &Path,
+    name: &str,
+    path: &Path,
+    mut on_output: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut fifo = open_fifo_with_timeout(name, path, FifoAccess::Read, FIFO_OPEN_TIMEOUT)?;
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match fifo.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                wait_readable_for(&fifo, 500);
+                continue;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if n > 0 {
+            on_output(&buf[..n])?;
+            continue;
+        }
+        // Every writer closed the pipe; keep following only if the daemon is still around.
+        if ensure_process_is_running(dir, name).is_err() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Waits up to `timeout_ms`, or forever if negative, for `file` to become
+/// readable or hung up. Returns false if the time ran out.
+fn wait_readable_for(file: &File, timeout_ms: i32) -> bool {
+    let mut fds = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, timeout_ms) > 0 }
+}
+
+/// How many lines of stderr `status` shows for a daemon that is not running.
+const STATUS_STDERR_LINES: usize = 10;
+
+/// The last `lines` lines the daemon's command wrote to stderr, if it wrote any.
+fn stderr_tail(dir: &Path, name: &str, lines: usize) -> Option<String> {
+    let log = logfile::read_all(&DaemonPaths::new(dir, name).stderr_log).ok()?;
+    let tail = last_lines(&log, lines);
+    (!tail.is_empty()).then(|| String::from_utf8_lossy(tail).into_owned())
+}
+
+/// Returns everything the daemon has printed to stdout, as retained by its log.
+fn read_stdout_log(dir: &Path, name: &str) -> Result<Vec<u8>> {
+    let paths = DaemonPaths::new(dir, name);
+    logfile::read_all(&paths.stdout_log).map_err(|e| {
+        anyhow::anyhow!("Cannot read stdout log of daemon '{}': {}", name, e)
+    })
+}
+
+/// Returns the last `lines` lines of `output`, not counting a final newline as
+/// the start of another line.
+fn last_lines(output: &[u8], lines: usize) -> &[u8] {
+    if lines == 0 {
+        return &[];
+    }
+    let body = output.strip_suffix(b"\n").unwrap_or(output);
+    let start = body
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| byte == b'\n')
+        .nth_back(lines - 1)
+        .map_or(0, |(pos, _)| pos + 1);
+    &output[start..]
+}
+
+/// Hands the daemon's merged stdout/stderr records to `on_record`, oldest first,
+/// keeping only the last `lines` if given. With `follow`, new records keep
+/// coming until the daemon goes away.
+fn read_logs(
+    dir: &Path,
+    name: &str,
+    lines: Option<usize>,
+    follow: bool,
+    mut on_record: impl FnMut(&Record) -> Result<()>,
+) -> Result<()> {
+    let paths = DaemonPaths::new(dir, name);
+    let (mut tail, history) = LogTail::open(&paths.merged_log)
+        .map_err(|e| anyhow::anyhow!("Cannot read log of daemon '{}': {}", name, e))?;
+    let records = logfile::parse_records(&history);
+    let skip = lines.map_or(0, |lines| records.len().saturating_sub(lines));
+    for record in &records[skip..] {
+        on_record(record)?;
+    }
+    if !follow {
+        return Ok(());
+    }
+    loop {
+        // Check first so that whatever was logged right before the exit is still shown.
+        let running = ensure_process_is_running(dir, name).is_ok();
+        for record in logfile::parse_records(&tail.poll()?) {
+            on_record(&record)?;
+        }
+        if !running {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Hands `consumer` the daemon's stdout from its log, starting where that
+/// consumer's previous read stopped. Every consumer has its own cursor, so any
+/// number of them can read the same daemon and each sees all of its output.
+/// The cursor is saved before the output is handed over, so nothing is ever
+/// delivered twice. With `follow`, new output keeps coming until the daemon
+/// goes away; otherwise at most `max_bytes` are read and the flag tells whether
+/// more was left for the next read.
+fn read_as_consumer(
+    dir: &Path,
+    name: &str,
+    consumer: &str,
+    max_bytes: Option<u64>,
+    follow: bool,
+    mut on_output: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<bool> {
+    ensure_pid_file(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let offset_path = paths.offset(consumer);
+    let mut cursor = load_cursor(&paths, consumer);
+    let read_after = |cursor, limit| {
+        logfile::read_after(&paths.stdout_log, cursor, limit).map_err(|e| {
+            anyhow::anyhow!("Cannot read stdout log of daemon '{}': {}", name, e)
+        })
+    };
+    loop {
+        // Check first so that whatever was logged right before the exit is still shown.
+        let running = ensure_process_is_running(dir, name).is_ok();
+        let (output, next) = read_after(cursor, max_bytes.filter(|_| !follow))?;
+        if cursor != Some(next) {
+            write_atomic(&offset_path, serde_json::to_string(&next)?.as_bytes())?;
+            cursor = Some(next);
+        }
+        if !output.is_empty() {
+            on_output(&output)?;
+        }
+        if !follow {
+            let truncated = max_bytes.is_some_and(|max| output.len() as u64 >= max)
+                && !read_after(cursor, Some(1))?.0.is_empty();
+            return Ok(truncated);
+        }
+        if !running {
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Where `consumer`'s previous read of the daemon's stdout log stopped, if it read before.
+fn load_cursor(paths: &DaemonPaths, consumer: &str) -> Option<logfile::Cursor> {
+    std::fs::read_to_string(paths.offset(consumer))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+}
+
+fn read_stderr(dir: &Path, name: &str) -> Result<Vec<u8>> {
+    ensure_process_is_running(dir, name)?;
+    let (output, _) = drain_fifo(name, &DaemonPaths::new(dir, name).stderr, None)?;
+    Ok(output)
+}
+
+/// Streams the daemon's stderr to our own stdout until the daemon goes away.
+fn follow_stderr(dir: &Path, name: &str, on_output: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    ensure_process_is_running(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    follow_fifo(dir, name, &paths.stderr, on_output)
+}
+
+/// The daemons found in a pipe directory.
+struct DaemonList {
+    /// Names of the daemons, in no particular order.
+    names: Vec<String>,
+    /// Files passed over because their names are not UTF-8.
+    skipped: Vec<PathBuf>,
+}
+
+fn list_daemons(dir: &Path) -> Result<DaemonList> {
+    ensure_pipe_dir_exists(dir)?;
+    find_daemons(dir)
+}
+
+/// Does what `list_daemons` does without creating `dir`, which is then an error.
+fn find_daemons(dir: &Path) -> Result<DaemonList> {
+    let mut daemons = DaemonList {
+        names: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            daemons.skipped.push(entry.path());
+            continue;
+        };
+        let Some(name) = file_name.strip_suffix(".pid") else {
+            continue;
+        };
+        // Other programs sharing the directory may leave their own PID files
+        // around; ours always come with the daemon's stdin FIFO next to them.
+        let is_daemon = std::fs::metadata(DaemonPaths::new(dir, name).stdin)
+            .is_ok_and(|metadata| metadata.file_type().is_fifo());
+        if is_daemon {
+            daemons.names.push(name.to_string());
+        }
+    }
+    Ok(daemons)
+}
+
+/// Returns the status of every daemon with a readable PID file, sorted by name.
+fn list_daemon_statuses(dir: &Path) -> Result<Vec<DaemonStatus>> {
+    Ok(daemon_statuses(dir, &list_daemons(dir)?.names))
+}
+
+/// Returns the status of each of `names` that has a readable PID file, sorted by name.
+fn daemon_statuses(dir: &Path, names: &[String]) -> Vec<DaemonStatus> {
+    let mut statuses: Vec<DaemonStatus> = names
+        .iter()
+        .filter_map(|name| daemon_status(dir, name).ok())
+        .collect();
+    statuses.sort_by(|a, b| a.name.cmp(&b.name));
+    statuses
+}
+
+/// Running daemons whose command, arguments and working directory are the same
+/// as those `meta` would start with, which usually means a service is about to
+/// be started twice under different names.
+fn find_duplicates(dir: &Path, meta: &DaemonMeta) -> Result<Vec<String>> {
+    // Without a cwd of their own, commands run wherever attyvo was started.
+    let cwd = meta.cwd.clone().or_else(|| std::env::current_dir().ok());
+    let duplicates = list_daemon_statuses(dir)?
+        .into_iter()
+        .filter(|status| status.running)
+        .filter(|status| {
+            status.meta.as_ref().is_some_and(|other| {
+                let other_cwd = other.cwd.clone().or_else(|| procfs::cwd(status.pid?));
+                other.command == meta.command
+                    && other.args == meta.args
+                    // If either directory is unknown, err on the side of warning.
+                    && (cwd.is_none() || other_cwd.is_none() || cwd == other_cwd)
+            })
+        })
+        .map(|status| status.name)
+        .collect();
+    Ok(duplicates)
+}
+
+fn is_process_alive(pid: i32) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+/// Whether `pid`, read from the daemon's PID file, is still its command. PIDs get
+/// reused, so a live process only counts if it started when the command did.
+fn is_command_alive(paths: &DaemonPaths, pid: i32) -> bool {
+    if !is_process_alive(pid) {
+        return false;
+    }
+    let recorded = load_meta(paths).ok().and_then(|meta| meta.pid_start_time);
+    match (recorded, procfs::start_time(pid)) {
+        (Some(recorded), Some(actual)) => recorded == actual,
+        // Without /proc, or for daemons from before start times were recorded,
+        // there is nothing better than trusting the PID.
+        _ => true,
+    }
+}
+
+fn ensure_process_is_running(dir: &Path, name: &str) -> Result<()> {
+    ensure_pid_file(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let pid: i32 = std::fs::read_to_string(&paths.pid)?.trim().parse()?;
+    // Once the exit file exists the command is gone, even if the daemon process
+    // has not been reaped yet.
+    if paths.exit.exists() || !is_command_alive(&paths, pid) {
+        return Err(Failure::NotRunning(name.to_string()).into());
+    }
+    Ok(())
+}
+
+/// What is known about a daemon from the files it keeps in the pipe directory.
+pub struct DaemonStatus {
+    /// Name of the daemon.
+    pub name: String,
+    /// PID of the daemon's command, or `None` for a daemon killed with its
+    /// logs kept, whose PID file is gone.
+    pub pid: Option<i32>,
+    /// Whether the command is still running.
+    pub running: bool,
+    /// Unix time the daemon started, from its metadata or else its PID file's mtime.
+    pub started_at: Option<u64>,
+    /// How the daemon was created, unless its metadata is missing or unreadable.
+    pub meta: Option<DaemonMeta>,
+    /// How the command ended, once it has.
+    pub exit: Option<ExitInfo>,
+    health: Option<HealthInfo>,
+}
+
+impl DaemonStatus {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "pid": self.pid,
+            "alive": self.running,
+            "state": self.state(),
+            "uptime_secs": self.uptime(),
+            "command": self.meta.as_ref().map(DaemonMeta::command_line),
+            "meta": self.meta,
+            "exit": self.exit,
+            "health": self.health,
+        })
+    }
+
+    /// Whether the daemon restarts its command, so a dead command may come back.
+    fn supervised(&self) -> bool {
+        self.meta.as_ref().is_some_and(|meta| meta.restart != RestartPolicy::No)
+    }
+
+    /// Seconds the daemon has been up, if it is running.
+    pub fn uptime(&self) -> Option<u64> {
+        let started_at = self.started_at.filter(|_| self.running)?;
+        Some(unix_now().saturating_sub(started_at))
+    }
+
+    /// A short description such as `running`, `restarting` or `exited with code 1`.
+    pub fn state(&self) -> String {
+        if self.meta.as_ref().is_some_and(|meta| meta.failed) {
+            return "failed".to_string();
+        }
+        if self.meta.as_ref().is_some_and(|meta| meta.timed_out) {
+            return "killed: timeout".to_string();
+        }
+        if self.pid.is_none() {
+            return "killed".to_string();
+        }
+        match (&self.exit, self.running) {
+            (Some(exit), _) => exit.to_string(),
+            (None, true) if self.meta.as_ref().is_some_and(|meta| meta.paused) => "paused".to_string(),
+            (None, true) => "running".to_string(),
+            (None, false) if self.supervised() => "restarting".to_string(),
+            (None, false) => "dead".to_string(),
+        }
+    }
+}
+
+fn daemon_status(dir: &Path, name: &str) -> Result<DaemonStatus> {
+    let paths = DaemonPaths::new(dir, name);
+    // `kill --keep-logs` leaves the metadata and exit status, but no PID file.
+    let kept = !paths.pid.exists() && paths.meta.exists() && paths.exit.exists();
+    let pid = if kept {
+        None
+    } else {
+        ensure_pid_file(dir, name)?;
+        Some(std::fs::read_to_string(&paths.pid)?.trim().parse()?)
+    };
+    let meta = load_meta(&paths).ok();
+    let exit = load_exit(&paths).ok();
+    let running = exit.is_none() && pid.is_some_and(|pid| is_command_alive(&paths, pid));
+    let health = std::fs::read_to_string(&paths.health)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok());
+    // Without metadata, e.g. after a crash mid-create, the PID file was written at startup.
+    let started_at = meta.as_ref().map(|meta| meta.started_at).or_else(|| {
+        let modified = std::fs::metadata(&paths.pid).ok()?.modified().ok()?;
+        Some(modified.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs())
+    });
+    Ok(DaemonStatus {
+        name: name.to_string(),
+        pid,
+        running,
+        started_at,
+        meta,
+        exit,
+        health,
+    })
+}
+
+/// Exit code used by `wait` when the timeout elapses, matching `timeout(1)`.
+const WAIT_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Blocks until the daemon's command finishes and returns the exit code the CLI
+/// should propagate, or `None` if `timeout` elapsed first.
+fn wait_daemon(dir: &Path, name: &str, timeout: Option<Duration>) -> Result<Option<i32>> {
+    ensure_pid_file(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let deadline = timeout.and_then(deadline_after);
+    let supervised = load_meta(&paths).is_ok_and(|meta| meta.restart != RestartPolicy::No);
+    let mut pid: i32 = std::fs::read_to_string(&paths.pid)?.trim().parse()?;
+    loop {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if !wait_for_exit(&paths, pid, remaining) {
+            return Ok(None);
+        }
+        if !supervised || paths.exit.exists() || !paths.pid.exists() {
+            break;
+        }
+        // The command may be about to be restarted; follow it to its new PID.
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        if let Ok(new_pid) = std::fs::read_to_string(&paths.pid)?.trim().parse() {
+            pid = new_pid;
+        }
+    }
+
+    let exit = load_exit(&paths).map_err(|_| {
+        anyhow::anyhow!("Daemon '{}' exited without recording an exit status", name)
+    })?;
+    Ok(Some(exit.exit_code()))
+}
+
+/// The instant `timeout` from now, or `None` for a timeout too long to fall
+/// within the `Instant` range, which callers take to mean no deadline at all.
+fn deadline_after(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+/// Polls until the daemon's command has finished, returning `false` if `timeout`
+/// elapsed first.
+fn wait_for_exit(paths: &DaemonPaths, pid: i32, timeout: Option<Duration>) -> bool {
+    let deadline = timeout.and_then(deadline_after);
+    while !paths.exit.exists() && is_command_alive(paths, pid) {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    // The daemon writes the exit file right after reaping the command, so give it
+    // a moment to land before callers go looking for it.
+    let settle = Instant::now() + Duration::from_millis(500);
+    while !paths.exit.exists() && paths.pid.exists() && Instant::now() < settle {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Parses a signal given by name (`HUP`, `SIGUSR1`, ...) or by number.
+fn parse_signal(s: &str) -> Result<i32, String> {
+    if let Ok(number) = s.parse::<i32>() {
+        return Ok(number);
+    }
+    let upper = s.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    let signal = match name {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "TERM" => libc::SIGTERM,
+        "ALRM" => libc::SIGALRM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "WINCH" => libc::SIGWINCH,
+        _ => return Err(format!("unknown signal '{}'", s)),
+    };
+    Ok(signal)
+}
+
+fn signal_daemon(dir: &Path, name: &str, signal: i32) -> Result<()> {
+    ensure_process_is_running(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let pid: i32 = std::fs::read_to_string(&paths.pid)?.trim().parse()?;
+    log::debug!("Sending signal {} to daemon '{}' (PID {})", signal, name, pid);
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to send signal {} to daemon '{}': {}",
+            signal,
+            name,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Sends SIGTERM to the command with PID `pid`, continuing its process group as
+/// well in case it was paused, since a stopped process cannot act on the signal.
+fn terminate(pid: i32) {
+    unsafe {
+        libc::kill(pid, libc::SIGTERM);
+        libc::killpg(pid, libc::SIGCONT);
+    }
+}
+
+/// Freezes the daemon's command, and whatever it started, with SIGSTOP until
+/// `resume_daemon`. The metadata notes it, so that `status` and `list` show it.
+fn pause_daemon(dir: &Path, name: &str) -> Result<()> {
+    set_paused(dir, name, true)
+}
+
+/// Lets a command frozen by `pause_daemon` carry on with SIGCONT.
+fn resume_daemon(dir: &Path, name: &str) -> Result<()> {
+    set_paused(dir, name, false)
+}
+
+fn set_paused(dir: &Path, name: &str, paused: bool) -> Result<()> {
+    ensure_process_is_running(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let pid: i32 = std::fs::read_to_string(&paths.pid)?.trim().parse()?;
+    let (signal, event) = if paused {
+        (libc::SIGSTOP, "paused")
+    } else {
+        (libc::SIGCONT, "resumed")
+    };
+    log::debug!("Sending signal {} to process group of daemon '{}' (PID {})", signal, name, pid);
+    // The command leads its own session, so its process group holds all it started.
+    if unsafe { libc::killpg(pid, signal) } != 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to send signal {} to daemon '{}': {}",
+            signal,
+            name,
+            std::io::Error::last_os_error()
+        ));
+    }
+    let mut meta = load_meta(&paths)?;
+    meta.paused = paused;
+    save_meta(&paths, &meta)?;
+    events::publish(dir, name, event, serde_json::json!({}));
+    Ok(())
+}
+
+/// How long `kill` waits after SIGTERM before resorting to SIGKILL.
+const DEFAULT_KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `create --wait-ready` waits for the daemon by default.
+const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a daemon's command was brought down by `kill`.
+pub enum KillOutcome {
+    /// The command had already exited.
+    AlreadyStopped,
+    /// The command exited after SIGTERM.
+    Graceful,
+    /// The command ignored SIGTERM for the whole grace period and got SIGKILL.
+    Forced,
+}
+
+/// Sends SIGTERM to the command and escalates to SIGKILL if it is still alive
+/// after `grace`.
+fn stop_process(paths: &DaemonPaths, pid: i32, grace: Duration) -> KillOutcome {
+    tell_supervisor_to_stop(paths);
+    if paths.exit.exists() || !is_command_alive(paths, pid) {
+        return KillOutcome::AlreadyStopped;
+    }
+    log::debug!("Sending SIGTERM to daemon '{}' (PID {})", paths.name, pid);
+    terminate(pid);
+    if wait_for_exit(paths, pid, Some(grace)) {
+        return KillOutcome::Graceful;
+    }
+    log::debug!(
+        "Daemon '{}' (PID {}) ignored SIGTERM for {:?}; sending SIGKILL",
+        paths.name,
+        pid,
+        grace
+    );
+    unsafe {
+        libc::kill(pid, libc::SIGKILL);
+    }
+    wait_for_exit(paths, pid, Some(Duration::from_secs(1)));
+    KillOutcome::Forced
+}
+
+/// Tells a supervising daemon not to restart its command once it goes down, or,
+/// while it waits between restarts, not to start it again at all. The daemon
+/// keeps its control pipe open for as long as it lives, so if nobody has it
+/// open there is nobody to tell.
+fn tell_supervisor_to_stop(paths: &DaemonPaths) {
+    if let Ok(mut control) =
+        open_fifo_with_timeout(&paths.name, &paths.control, FifoAccess::Write, Duration::ZERO)
+    {
+        writeln!(control, "stop").ok();
+    }
+}
+
+/// Stops daemon `name` and removes all of its files, or with `keep_logs` only
+/// its PID file and pipes, recording how the command ended if it did not. With
+/// `force`, a missing or unreadable PID file is not an error: whatever files are
+/// left are removed.
+fn kill_daemon(
+    dir: &Path,
+    name: &str,
+    grace: Duration,
+    force: bool,
+    keep_logs: bool,
+) -> Result<KillOutcome> {
+    log::debug!("Killing daemon '{}' (grace {:?}, force: {})", name, grace, force);
+    let paths = DaemonPaths::new(dir, name);
+    let remove_files = |paths: &DaemonPaths, outcome: &KillOutcome| {
+        if !keep_logs {
+            return remove_daemon_files(paths);
+        }
+        // Unless the daemon recorded it itself, the exit status is what we did to it.
+        if !paths.exit.exists() {
+            let signal = match outcome {
+                KillOutcome::AlreadyStopped => None,
+                KillOutcome::Graceful => Some(libc::SIGTERM),
+                KillOutcome::Forced => Some(libc::SIGKILL),
+            };
+            let exit = ExitInfo { code: None, signal };
+            write_atomic(&paths.exit, serde_json::to_string(&exit)?.as_bytes())?;
+        }
+        remove_live_files(paths)
+    };
+    let pid = if force {
+        if !paths.all().iter().any(|path| path.exists()) {
+            return Err(anyhow::anyhow!("No files found for daemon '{}'", name));
+        }
+        std::fs::read_to_string(&paths.pid)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<i32>().ok())
+            .filter(|&pid| pid > 0)
+    } else {
+        ensure_pid_file(dir, name)?;
+        Some(std::fs::read_to_string(&paths.pid)?.trim().parse()?)
+    };
+    let Some(pid) = pid else {
+        tell_supervisor_to_stop(&paths);
+        log::debug!("Daemon '{}' has no PID; removing its files", name);
+        remove_files(&paths, &KillOutcome::AlreadyStopped)?;
+        return Ok(KillOutcome::AlreadyStopped);
+    };
+    let outcome = stop_process(&paths, pid, grace);
+    if is_command_alive(&paths, pid) && !paths.exit.exists() {
+        return Err(anyhow::anyhow!(
+            "Daemon '{}' (PID {}) is still running after SIGKILL",
+            name,
+            pid
+        ));
+    }
+
+    remove_files(&paths, &outcome)?;
+    events::publish(dir, name, "killed", serde_json::json!({}));
+    Ok(outcome)
+}
+
+/// Removes every file a daemon leaves in the pipe directory.
+fn remove_daemon_files(paths: &DaemonPaths) -> Result<()> {
+    for path in paths.all() {
+        remove_if_exists(&path)?;
+    }
+    for (_, path) in paths.offsets() {
+        remove_if_exists(&path)?;
+    }
+    Ok(())
+}
+
+/// Moves every file of daemon `old` over to `new`, telling the daemon if it is running.
+fn rename_daemon(dir: &Path, old: &str, new: &str) -> Result<()> {
+    ensure_pid_file(dir, old)?;
+    let (old_paths, new_paths) = (DaemonPaths::new(dir, old), DaemonPaths::new(dir, new));
+    if new_paths.all().iter().any(|path| path.exists()) {
+        return Err(Failure::AlreadyExists(new.to_string()).into());
+    }
+    let running = ensure_process_is_running(dir, old).is_ok();
+    for (from, to) in old_paths.all().iter().zip(new_paths.all()) {
+        if from.exists() {
+            std::fs::rename(from, to)?;
+        }
+    }
+    for (consumer, path) in old_paths.offsets() {
+        std::fs::rename(path, new_paths.offset(&consumer))?;
+    }
+    if running {
+        send_control(dir, new, &format!("rename {}", new))?;
+    }
+    Ok(())
+}
+
+/// Removes the PID file and pipes of a daemon, keeping its metadata, logs and
+/// the record of how its command ended.
+fn remove_live_files(paths: &DaemonPaths) -> Result<()> {
+    remove_if_exists(&paths.pid)?;
+    remove_if_exists(&paths.stdin)?;
+    remove_if_exists(&paths.stdout)?;
+    remove_if_exists(&paths.stderr)?;
+    remove_if_exists(&paths.control)?;
+    Ok(())
+}
+
+/// Removes the files of a daemon's last run, keeping its metadata and logs.
+fn remove_runtime_files(paths: &DaemonPaths) -> Result<()> {
+    remove_live_files(paths)?;
+    remove_if_exists(&paths.exit)?;
+    remove_if_exists(&paths.health)?;
+    Ok(())
+}
+
+fn restart_daemon(dir: &Path, name: &str, ready_timeout: Option<Duration>) -> Result<()> {
+    let paths = DaemonPaths::new(dir, name);
+    let mut meta = load_meta(&paths).map_err(|e| {
+        anyhow::anyhow!("Cannot restart daemon '{}': no usable metadata ({})", name, e)
+    })?;
+
+    if ensure_process_is_running(dir, name).is_ok() {
+        let pid: i32 = std::fs::read_to_string(&paths.pid)?.trim().parse()?;
+        stop_process(&paths, pid, DEFAULT_KILL_TIMEOUT);
+    } else {
+        tell_supervisor_to_stop(&paths);
+    }
+    remove_runtime_files(&paths)?;
+
+    meta.reset_runs();
+    create(dir, name, &meta, ready_timeout)
+}
+
+/// Creates daemon `dst` with the settings of daemon `src`, replacing the
+/// arguments in `arg_overrides` (position counting from 1, new value) and
+/// setting the variables in `env` on top of `src`'s environment.
+fn clone_daemon(
+    dir: &Path,
+    src: &str,
+    dst: &str,
+    arg_overrides: &[(usize, String)],
+    env: &[(String, String)],
+) -> Result<()> {
+    let src_paths = DaemonPaths::new(dir, src);
+    if !src_paths.meta.exists() {
+        return Err(Failure::NotFound(src.to_string()).into());
+    }
+    let mut meta = load_meta(&src_paths).map_err(|e| {
+        anyhow::anyhow!("Cannot clone daemon '{}': no usable metadata ({})", src, e)
+    })?;
+    // Unlike create, refuse to reuse the files of a dead daemon: they may be wanted.
+    if DaemonPaths::new(dir, dst).all().iter().any(|path| path.exists()) {
+        return Err(Failure::AlreadyExists(dst.to_string()).into());
+    }
+    for (position, value) in arg_overrides {
+        let count = meta.args.len();
+        let arg = position
+            .checked_sub(1)
+            .and_then(|index| meta.args.get_mut(index))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Daemon '{}' has {} argument(s), so there is no argument {}",
+                    src,
+                    count,
+                    position
+                )
+            })?;
+        *arg = value.clone();
+    }
+    meta.env.extend(env.iter().cloned());
+    meta.reset_runs();
+    create(dir, dst, &meta, Some(DEFAULT_READY_TIMEOUT))
+}
+
+/// Removes the PID files and pipes of every daemon whose command is no longer
+/// running and that no supervisor will restart.
+fn prune_daemons(dir: &Path) -> Result<Vec<String>> {
+    let mut pruned = Vec::new();
+    for daemon in list_daemons(dir)?.names {
+        let paths = DaemonPaths::new(dir, &daemon);
+        // A supervisor waiting out its restart delay still needs its FIFOs.
+        if ensure_process_is_running(dir, &daemon).is_err()
+            && !load_meta(&paths).is_ok_and(|meta| meta.supervisor_alive())
+        {
+            remove_runtime_files(&paths)?;
+            pruned.push(daemon);
+        }
+    }
+    Ok(pruned)
+}
+
+/// What `clean` removed on behalf of one daemon.
+struct Cleaned {
+    name: String,
+    files: usize,
+    bytes: u64,
+}
+
+/// Names of the daemons that have any file left in `dir`, PID file or not.
+fn leftover_names(dir: &Path) -> Result<BTreeSet<String>> {
+    ensure_pipe_dir_exists(dir)?;
+    let mut names = BTreeSet::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        // Names have no dots, so a file belongs to the name before its first
+        // dot, or, for the pipes, before the underscore of the pipe's suffix.
+        let (name, rest) = file_name
+            .split_once('.')
+            .or_else(|| file_name.rsplit_once('_'))
+            .unwrap_or((file_name, ""));
+        if validate_name(name).is_err() {
+            continue;
+        }
+        let is_offset = rest
+            .strip_suffix(".offset")
+            .is_some_and(|consumer| validate_consumer(consumer).is_ok());
+        let is_relay = rest
+            .strip_suffix(".relay")
+            .is_some_and(|dst| validate_name(dst).is_ok());
+        if is_offset || is_relay || DaemonPaths::new(dir, name).all().contains(&path) {
+            names.insert(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Whether daemon `name` is, or may soon be, using its files: its command is
+/// running, it is about to restart it, or it is still being created.
+fn daemon_in_use(dir: &Path, name: &str) -> bool {
+    let paths = DaemonPaths::new(dir, name);
+    if paths.lock.exists() && CreateLock::acquire(&paths, name).is_err() {
+        return true;
+    }
+    command_active(dir, name)
+}
+
+/// Whether daemon `name`'s command is running or about to be restarted.
+fn command_active(dir: &Path, name: &str) -> bool {
+    daemon_status(dir, name)
+        .is_ok_and(|status| status.running || (status.exit.is_none() && status.supervised()))
+}
+
+/// Removes the leftover logs, metadata and cursors of daemon `name`, or of
+/// every daemon that is no longer running.
+fn clean_daemons(dir: &Path, name: Option<&str>) -> Result<Vec<Cleaned>> {
+    let names = leftover_names(dir)?;
+    if let Some(name) = name
+        && !names.contains(name)
+    {
+        return Err(Failure::NotFound(name.to_string()).into());
+    }
+    let (in_use, unused): (Vec<String>, Vec<String>) =
+        names.into_iter().partition(|name| daemon_in_use(dir, name));
+    if let Some(name) = name
+        && in_use.iter().any(|daemon| daemon == name)
+    {
+        return Err(anyhow::anyhow!(
+            "Daemon '{}' is still running; kill it first",
+            name
+        ));
+    }
+    let mut cleaned = Vec::new();
+    for daemon in unused {
+        if name.is_some_and(|name| name != daemon) {
+            continue;
+        }
+        let paths = DaemonPaths::new(dir, &daemon);
+        let offsets = paths.offsets().into_iter().map(|(_, path)| path);
+        let (mut files, mut bytes) = (0, 0);
+        for path in paths.all().into_iter().chain(offsets) {
+            if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+                remove_if_exists(&path)?;
+                files += 1;
+                bytes += metadata.len();
+            }
+        }
+        if files > 0 {
+            cleaned.push(Cleaned {
+                name: daemon,
+                files,
+                bytes,
+            });
+        }
+    }
+    Ok(cleaned)
+}
+
+/// Matches `name` against a shell-style glob where `*` stands for any run of
+/// characters and `?` for exactly one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the most recent `*` if the rest fails to match.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// What came of acting on many daemons at once.
+struct BatchOutcome {
+    /// Daemons the action succeeded for.
+    done: Vec<String>,
+    /// Daemons the action failed for, with the reason.
+    failed: Vec<(String, anyhow::Error)>,
+    /// Files passed over because their names are not UTF-8.
+    skipped: Vec<PathBuf>,
+}
+
+impl BatchOutcome {
+    fn new(skipped: Vec<PathBuf>) -> Self {
+        BatchOutcome {
+            done: Vec::new(),
+            failed: Vec::new(),
+            skipped,
+        }
+    }
+
+    fn record(&mut self, daemon: String, result: Result<()>) {
+        match result {
+            Ok(()) => self.done.push(daemon),
+            Err(e) => self.failed.push((daemon, e)),
+        }
+    }
+}
+
+/// Kills every daemon, or only those whose name matches the glob `filter`.
+fn kill_all_daemons(dir: &Path, grace: Duration, filter: Option<&str>) -> Result<BatchOutcome> {
+    let daemons = list_daemons(dir)?;
+    let mut outcome = BatchOutcome::new(daemons.skipped);
+    for daemon in daemons.names {
+        if filter.is_some_and(|pattern| !glob_match(pattern, &daemon)) {
+            continue;
+        }
+        let result = kill_daemon(dir, &daemon, grace, false, false).map(drop);
+        outcome.record(daemon, result);
+    }
+    Ok(outcome)
+}
+
+/// Sends `signal` to the command of every running daemon matching `filter`,
+/// leaving the daemons running.
+fn signal_all_daemons(dir: &Path, signal: i32, filter: Option<&str>) -> Result<BatchOutcome> {
+    let daemons = list_daemons(dir)?;
+    let mut outcome = BatchOutcome::new(daemons.skipped);
+    for daemon in daemons.names {
+        if filter.is_some_and(|pattern| !glob_match(pattern, &daemon)) {
+            continue;
+        }
+        if ensure_process_is_running(dir, &daemon).is_err() {
+            continue;
+        }
+        let result = signal_daemon(dir, &daemon, signal);
+        outcome.record(daemon, result);
+    }
+    Ok(outcome)
+}
+
+fn resolve_cwd(cwd: &Path) -> Result<PathBuf> {
+    let cwd = std::path::absolute(cwd)?;
+    if !cwd.is_dir() {
+        return Err(anyhow::anyhow!(
+            "Working directory does not exist: {}",
+            cwd.display()
+        ));
+    }
+    Ok(cwd)
+}
+
+/// Checks that `name` is safe to use as part of a file name inside the pipe
+/// directory: ASCII letters, digits, `_` and `-`. Dots are reserved for the
+/// suffixes of the daemon's files, so no daemon's files can pass for another's.
+fn validate_name(name: &str) -> Result<String, String> {
+    if name.is_empty() {
+        return Err("daemon name must not be empty".to_string());
+    }
+    if name.len() > 100 {
+        return Err(format!("daemon name '{}' is longer than 100 characters", name));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-')))
+    {
+        return Err(format!("daemon name '{}' contains invalid character {:?}", name, c));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_consumer(id: &str) -> Result<String, String> {
+    if id.is_empty() || id.len() > 100 {
+        return Err("consumer ID must be between 1 and 100 characters long".to_string());
+    }
+    if let Some(c) = id.chars().find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))) {
+        return Err(format!("consumer ID '{}' contains invalid character {:?}", id, c));
+    }
+    Ok(id.to_string())
+}
+
+/// The bytes a terminal sends for the key called `s`.
+fn parse_key(s: &str) -> Result<Vec<u8>, String> {
+    let lower = s.to_ascii_lowercase();
+    let ctrl = lower
+        .strip_prefix("ctrl-")
+        .or_else(|| lower.strip_prefix("ctrl+"))
+        .or_else(|| lower.strip_prefix('^').filter(|rest| !rest.is_empty()));
+    if let Some(key) = ctrl {
+        return match key.as_bytes() {
+            [c @ b'a'..=b'z'] => Ok(vec![c - b'a' + 1]),
+            [c @ (b'@' | b'[' | b'\\' | b']' | b'^' | b'_')] => Ok(vec![c & 0x1f]),
+            b"space" => Ok(vec![0]),
+            _ => Err(format!("unknown key '{}'", s)),
+        };
+    }
+    let bytes: &[u8] = match lower.as_str() {
+        "enter" | "return" => b"\r",
+        "tab" => b"\t",
+        "esc" | "escape" => b"\x1b",
+        "space" => b" ",
+        "backspace" => b"\x7f",
+        "delete" | "del" => b"\x1b[3~",
+        "insert" => b"\x1b[2~",
+        "home" => b"\x1b[H",
+        "end" => b"\x1b[F",
+        "pageup" => b"\x1b[5~",
+        "pagedown" => b"\x1b[6~",
+        "up" => b"\x1b[A",
+        "down" => b"\x1b[B",
+        "right" => b"\x1b[C",
+        "left" => b"\x1b[D",
+        "f1" => b"\x1bOP",
+        "f2" => b"\x1bOQ",
+        "f3" => b"\x1bOR",
+        "f4" => b"\x1bOS",
+        "f5" => b"\x1b[15~",
+        "f6" => b"\x1b[17~",
+        "f7" => b"\x1b[18~",
+        "f8" => b"\x1b[19~",
+        "f9" => b"\x1b[20~",
+        "f10" => b"\x1b[21~",
+        "f11" => b"\x1b[23~",
+        "f12" => b"\x1b[24~",
+        // Any other single character is typed as is.
+        _ if s.chars().count() == 1 => s.as_bytes(),
+        _ => return Err(format!("unknown key '{}'", s)),
+    };
+    Ok(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_names() {
+        for name in ["web", "db-1", "my_app", "A9"] {
+            assert_eq!(validate_name(name), Ok(name.to_string()));
+        }
+        assert!(validate_name(&"a".repeat(100)).is_ok());
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_plain_file_names() {
+        for name in ["", "a.stdout", ".hidden", "..", "a/b", "a b", "née"] {
+            assert!(validate_name(name).is_err(), "{:?} was accepted", name);
+        }
+        assert!(validate_name(&"a".repeat(101)).is_err());
+    }
+
+    #[test]
+    fn names_do_not_share_files() {
+        let dir = Path::new("/tmp/pipes");
+        let a = DaemonPaths::new(dir, "a").all();
+        for other in ["a_stdout", "a-stdout", "a_stdin_x"] {
+            let other = DaemonPaths::new(dir, other).all();
+            assert!(a.iter().all(|path| !other.contains(path)));
+        }
+    }
+
+    #[test]
+    fn unescapes_backslash_escapes() {
+        assert_eq!(
+            unescape(r"a\tb\r\n\e[2J\0\\\x41é").unwrap(),
+            b"a\tb\r\n\x1b[2J\0\\A\xc3\xa9"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_escapes() {
+        assert_eq!(
+            unescape(r"\q").unwrap_err().to_string(),
+            r"Unknown escape '\q'"
+        );
+        assert_eq!(
+            unescape(r"\x4").unwrap_err().to_string(),
+            r"Invalid escape '\x4'"
+        );
+        assert_eq!(
+            unescape(r"\xzz").unwrap_err().to_string(),
+            r"Invalid escape '\xzz'"
+        );
+        assert_eq!(
+            unescape("end\\").unwrap_err().to_string(),
+            "Trailing backslash in message"
+        );
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("web-*", "web-1"));
+        assert!(glob_match("web-*", "web-"));
+        assert!(glob_match("*-worker", "mail-worker"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("web-*", "db-1"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("*a*b", "xxbxxa"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVsbG8").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVs\nbG8h\n").unwrap(), b"hello!");
+        assert_eq!(decode_base64("AP8+/w==").unwrap(), [0x00, 0xff, 0x3e, 0xff]);
+        assert_eq!(decode_base64("").unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_bad_base64() {
+        assert_eq!(
+            decode_base64("aGV*").unwrap_err().to_string(),
+            "Invalid base64 character '*'"
+        );
+        assert_eq!(
+            decode_base64("aGVsb").unwrap_err().to_string(),
+            "Truncated base64 input"
+        );
+    }
+
+    #[test]
+    fn parses_control_keys() {
+        assert_eq!(parse_key("ctrl-c").unwrap(), [0x03]);
+        assert_eq!(parse_key("Ctrl+D").unwrap(), [0x04]);
+        assert_eq!(parse_key("^z").unwrap(), [0x1a]);
+        assert_eq!(parse_key("ctrl-\\").unwrap(), [0x1c]);
+        assert_eq!(parse_key("ctrl-space").unwrap(), [0x00]);
+        assert!(parse_key("ctrl-1").is_err());
+    }
+
+    #[test]
+    fn parses_named_and_single_keys() {
+        assert_eq!(parse_key("enter").unwrap(), b"\r");
+        assert_eq!(parse_key("ESC").unwrap(), b"\x1b");
+        assert_eq!(parse_key("up").unwrap(), b"\x1b[A");
+        assert_eq!(parse_key("f5").unwrap(), b"\x1b[15~");
+        assert_eq!(parse_key("q").unwrap(), b"q");
+        assert_eq!(parse_key("^").unwrap(), b"^");
+        assert_eq!(parse_key("nope").unwrap_err(), "unknown key 'nope'");
+    }
+}
@@ -0,0 +1,411 @@
+//! Size-rotated log files that keep a persistent copy of a daemon's output.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// An append-only log that is moved aside to `<path>.1` once it grows past `max_size`.
+pub struct RotatingLog {
+    path: PathBuf,
+    max_size: u64,
+    file: File,
+    size: u64,
+}
+
+impl RotatingLog {
+    pub fn open(path: &Path, max_size: u64) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLog {
+            path: path.to_path_buf(),
+            max_size,
+            file,
+            size,
+        })
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Throws away everything logged so far, including the previous generation.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        match std::fs::remove_file(rotated_path(&self.path)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.file.set_len(0)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Points the log at the file's new location after it was renamed underneath us.
+    pub fn set_path(&mut self, path: &Path) {
+        self.path = path.to_path_buf();
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        std::fs::rename(&self.path, rotated_path(&self.path))?;
+        self.file = File::options()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Path of the previous generation of the log at `path`.
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+/// Reads the whole retained history of the log at `path`, oldest bytes first.
+pub fn read_all(path: &Path) -> io::Result<Vec<u8>> {
+    let mut contents = match std::fs::read(rotated_path(path)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    contents.extend(std::fs::read(path)?);
+    Ok(contents)
+}
+
+/// How far a reader has got through a `RotatingLog`: a position in the file
+/// with inode `ino`, which stays the same when that file is rotated aside.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub ino: u64,
+    pub pos: u64,
+}
+
+/// A cursor just past everything logged at `path` so far.
+pub fn end_cursor(path: &Path) -> io::Result<Cursor> {
+    let metadata = std::fs::metadata(path)?;
+    Ok(Cursor {
+        ino: metadata.ino(),
+        pos: metadata.len(),
+    })
+}
+
+/// Reads everything logged at `path` after `cursor`, or the whole retained
+/// history without one, and returns it with the cursor at its end. Output
+/// from generations that were rotated out entirely is skipped. With `limit`,
+/// at most that many bytes are read and the cursor stops after them.
+pub fn read_after(
+    path: &Path,
+    cursor: Option<Cursor>,
+    limit: Option<u64>,
+) -> io::Result<(Vec<u8>, Cursor)> {
+    let mut current = File::open(path)?;
+    let ino = current.metadata()?.ino();
+    let limit = limit.unwrap_or(u64::MAX);
+    let mut contents = Vec::new();
+    let start = match cursor {
+        Some(cursor) if cursor.ino == ino => cursor.pos,
+        _ => {
+            match File::open(rotated_path(path)) {
+                Ok(mut rotated) => {
+                    let rotated_ino = rotated.metadata()?.ino();
+                    let mut pos = 0;
+                    if let Some(cursor) = cursor.filter(|cursor| cursor.ino == rotated_ino) {
+                        pos = rotated.seek(SeekFrom::Start(cursor.pos))?;
+                    }
+                    pos += (&mut rotated).take(limit).read_to_end(&mut contents)? as u64;
+                    if contents.len() as u64 >= limit {
+                        return Ok((
+                            contents,
+                            Cursor {
+                                ino: rotated_ino,
+                                pos,
+                            },
+                        ));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            0
+        }
+    };
+    // A log that shrank was truncated for a new run; all of it is new.
+    let start = if start > current.metadata()?.len() {
+        0
+    } else {
+        start
+    };
+    current.seek(SeekFrom::Start(start))?;
+    let remaining = limit - contents.len() as u64;
+    let read = current.take(remaining).read_to_end(&mut contents)?;
+    let cursor = Cursor {
+        ino,
+        pos: start + read as u64,
+    };
+    Ok((contents, cursor))
+}
+
+/// Which of the command's output streams a line of the merged log came from.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stream {
+    Out,
+    Err,
+    /// Messages from attyvo itself, such as restarts.
+    Sys,
+}
+
+impl Stream {
+    pub fn label(self) -> &'static str {
+        match self {
+            Stream::Out => "out",
+            Stream::Err => "err",
+            Stream::Sys => "sys",
+        }
+    }
+}
+
+/// A log that interleaves complete lines from stdout and stderr in the order
+/// they arrived, one `<unix millis> <out|err> <text>` record per line.
+pub struct MergedLog {
+    log: RotatingLog,
+    pending: [Vec<u8>; 2],
+}
+
+impl MergedLog {
+    pub fn open(path: &Path, max_size: u64) -> io::Result<Self> {
+        Ok(MergedLog {
+            log: RotatingLog::open(path, max_size)?,
+            pending: [Vec::new(), Vec::new()],
+        })
+    }
+
+    pub fn write(&mut self, stream: Stream, buf: &[u8]) -> io::Result<()> {
+        self.pending[stream as usize].extend_from_slice(buf);
+        while let Some(pos) = self.pending[stream as usize]
+            .iter()
+            .position(|&b| b == b'\n')
+        {
+            let line: Vec<u8> = self.pending[stream as usize].drain(..=pos).collect();
+            self.write_record(stream, &line[..pos])?;
+        }
+        Ok(())
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        self.log.set_path(path);
+    }
+
+    /// Records a message from attyvo itself.
+    pub fn note(&mut self, message: &str) -> io::Result<()> {
+        self.write_record(Stream::Sys, message.as_bytes())
+    }
+
+    /// Writes out any partial lines that never got their newline.
+    pub fn finish(&mut self) -> io::Result<()> {
+        for stream in [Stream::Out, Stream::Err] {
+            let line = std::mem::take(&mut self.pending[stream as usize]);
+            if !line.is_empty() {
+                self.write_record(stream, &line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_record(&mut self, stream: Stream, line: &[u8]) -> io::Result<()> {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut record = format!("{} {} ", millis, stream.label()).into_bytes();
+        record.extend_from_slice(line);
+        record.push(b'\n');
+        self.log.write(&record)
+    }
+}
+
+/// One line of a merged log.
+pub struct Record {
+    pub millis: u64,
+    pub stream: Stream,
+    pub line: Vec<u8>,
+}
+
+impl Record {
+    pub fn parse(record: &[u8]) -> Option<Record> {
+        let mut parts = record.splitn(3, |&b| b == b' ');
+        let millis = std::str::from_utf8(parts.next()?).ok()?.parse().ok()?;
+        let stream = match parts.next()? {
+            b"out" => Stream::Out,
+            b"err" => Stream::Err,
+            b"sys" => Stream::Sys,
+            _ => return None,
+        };
+        let line = parts.next().unwrap_or_default().to_vec();
+        Some(Record {
+            millis,
+            stream,
+            line,
+        })
+    }
+}
+
+/// Parses every complete record in `contents`.
+pub fn parse_records(contents: &[u8]) -> Vec<Record> {
+    contents
+        .split(|&b| b == b'\n')
+        .filter_map(Record::parse)
+        .collect()
+}
+
+/// Follows a log at `path` across rotations, handing out complete lines only.
+pub struct LogTail {
+    path: PathBuf,
+    file: File,
+    partial: Vec<u8>,
+}
+
+impl LogTail {
+    /// Opens the log and returns it together with its retained history.
+    pub fn open(path: &Path) -> io::Result<(LogTail, Vec<u8>)> {
+        let mut history = match std::fs::read(rotated_path(path)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut file = File::open(path)?;
+        file.read_to_end(&mut history)?;
+        let mut tail = LogTail {
+            path: path.to_path_buf(),
+            file,
+            partial: Vec::new(),
+        };
+        let complete = history
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |pos| pos + 1);
+        tail.partial = history.split_off(complete);
+        Ok((tail, history))
+    }
+
+    /// Returns the complete lines appended since the last call.
+    pub fn poll(&mut self) -> io::Result<Vec<u8>> {
+        let mut contents = std::mem::take(&mut self.partial);
+        self.file.read_to_end(&mut contents)?;
+        // After a rotation the path names a fresh file; finish the old one first.
+        if let Ok(metadata) = std::fs::metadata(&self.path)
+            && metadata.ino() != self.file.metadata()?.ino()
+        {
+            self.file = File::open(&self.path)?;
+            self.file.read_to_end(&mut contents)?;
+        }
+        let complete = contents
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |pos| pos + 1);
+        self.partial = contents.split_off(complete);
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_records_and_skips_malformed_lines() {
+        let records =
+            parse_records(b"1 out hello world\n2 err oops\nnot a record\n3 sys\n4 bad x\n");
+        let parsed: Vec<_> = records
+            .iter()
+            .map(|record| (record.millis, record.stream.label(), record.line.as_slice()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (1, "out", &b"hello world"[..]),
+                (2, "err", b"oops"),
+                (3, "sys", b"")
+            ]
+        );
+    }
+
+    /// A fresh directory for one test's logs.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("attyvo-logfile-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_only_what_was_logged_after_the_cursor() {
+        let path = test_dir("cursor").join("out.log");
+        let mut log = RotatingLog::open(&path, 1024).unwrap();
+        log.write(b"first\n").unwrap();
+        let (contents, cursor) = read_after(&path, None, None).unwrap();
+        assert_eq!(contents, b"first\n");
+        log.write(b"second\n").unwrap();
+        let (contents, cursor) = read_after(&path, Some(cursor), None).unwrap();
+        assert_eq!(contents, b"second\n");
+        let (contents, _) = read_after(&path, Some(cursor), None).unwrap();
+        assert!(contents.is_empty());
+    }
+
+    #[test]
+    fn follows_the_cursor_across_a_rotation() {
+        let path = test_dir("rotation").join("out.log");
+        let mut log = RotatingLog::open(&path, 10).unwrap();
+        log.write(b"aaaaaa").unwrap();
+        let (_, cursor) = read_after(&path, None, None).unwrap();
+        log.write(b"bbbbbb").unwrap();
+        log.write(b"cc").unwrap();
+        assert!(rotated_path(&path).exists());
+        let (contents, _) = read_after(&path, Some(cursor), None).unwrap();
+        assert_eq!(contents, b"bbbbbbcc");
+        let (contents, _) = read_after(&path, None, None).unwrap();
+        assert_eq!(contents, b"aaaaaabbbbbbcc");
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let path = test_dir("limit").join("out.log");
+        let mut log = RotatingLog::open(&path, 1024).unwrap();
+        log.write(b"0123456789").unwrap();
+        let (contents, cursor) = read_after(&path, None, Some(4)).unwrap();
+        assert_eq!(contents, b"0123");
+        let (contents, _) = read_after(&path, Some(cursor), None).unwrap();
+        assert_eq!(contents, b"456789");
+    }
+
+    #[test]
+    fn rereads_a_log_truncated_for_a_new_run() {
+        let path = test_dir("truncated").join("out.log");
+        let mut log = RotatingLog::open(&path, 1024).unwrap();
+        log.write(b"old run output\n").unwrap();
+        let (_, cursor) = read_after(&path, None, None).unwrap();
+        log.truncate().unwrap();
+        log.write(b"new\n").unwrap();
+        let (contents, _) = read_after(&path, Some(cursor), None).unwrap();
+        assert_eq!(contents, b"new\n");
+    }
+}
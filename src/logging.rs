@@ -0,0 +1,89 @@
+//! attyvo's own diagnostics, as opposed to the logs of the commands it runs.
+//! Messages go through the `log` macros and are printed to stderr, or to the
+//! daemon's `.attyvo.log` file once it has detached and has no stderr left.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{format_timestamp, unix_millis};
+
+struct Logger {
+    /// Where messages go; stderr until `redirect` is called.
+    target: Mutex<Option<Target>>,
+}
+
+/// A log file, opened only once there is something to write to it.
+struct Target {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+static LOGGER: Logger = Logger {
+    target: Mutex::new(None),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:5} [{}] {}\n",
+            format_timestamp(unix_millis()),
+            record.level(),
+            std::process::id(),
+            record.args()
+        );
+        let Some(target) = &mut *self.target.lock().unwrap() else {
+            std::io::stderr().write_all(line.as_bytes()).ok();
+            return;
+        };
+        if target.file.is_none() {
+            target.file = File::options()
+                .create(true)
+                .append(true)
+                .open(&target.path)
+                .ok();
+        }
+        if let Some(file) = &mut target.file {
+            file.write_all(line.as_bytes()).ok();
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Turns logging on. `verbosity` counts `-v` flags: one shows debug messages,
+/// two or more also trace ones. Without any, `RUST_LOG` may name a level.
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => std::env::var("RUST_LOG")
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(LevelFilter::Warn),
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Sends further messages to the file at `path`, appending to it. The file is
+/// only created once the first message arrives.
+pub fn redirect(path: &Path) {
+    *LOGGER.target.lock().unwrap() = Some(Target {
+        path: path.to_path_buf(),
+        file: None,
+    });
+}
@@ -0,0 +1,184 @@
+//! The entry point for driving daemons from Rust rather than the command line.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    DEFAULT_CONSUMER, DaemonMeta, DaemonStatus, KillOutcome, daemon_status, error, error::Failure,
+    kill_daemon, list_daemon_statuses, pause_daemon, read_stderr, read_stdout, rename_daemon,
+    resize_daemon, resume_daemon, send_eof, signal_daemon, validate_consumer, validate_name,
+    wait_daemon, write,
+};
+
+/// Manages the daemons whose pipes and PID files live in one directory, as the
+/// `attyvo` command does with `--dir`. Daemons started here can be used from
+/// the command line and the other way round.
+pub struct DaemonManager {
+    dir: PathBuf,
+    program: PathBuf,
+}
+
+impl DaemonManager {
+    /// Manages the daemons in `dir`, which is created on first use if needed.
+    ///
+    /// Starting a daemon means forking, which is not safe in a process that may
+    /// have other threads, so `create` and `restart` run the `attyvo` executable
+    /// at `program` to do it. It must be the same version as this crate; a bare
+    /// name such as `"attyvo"` is looked up on the `PATH`.
+    pub fn new(dir: impl Into<PathBuf>, program: impl Into<PathBuf>) -> Self {
+        DaemonManager {
+            dir: dir.into(),
+            program: program.into(),
+        }
+    }
+
+    /// The directory holding the daemons' files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Starts `meta`'s command as daemon `name` and returns once it is confirmed
+    /// to be running. The daemon outlives this process.
+    pub fn create(&self, name: &str, meta: &DaemonMeta) -> Result<()> {
+        self.spawn(checked(name)?, Some(meta))
+    }
+
+    /// Writes `input` to the daemon's stdin as is; add a newline to submit a line.
+    pub fn write(&self, name: &str, input: &[u8]) -> Result<()> {
+        write(&self.dir, checked(name)?, input, false)
+    }
+
+    /// Signals end-of-file on the daemon's stdin once earlier input is delivered.
+    pub fn send_eof(&self, name: &str) -> Result<()> {
+        send_eof(&self.dir, checked(name)?)
+    }
+
+    /// Returns the stdout the daemon logged since the previous `read_stdout`,
+    /// without waiting for more. This continues the same reader as a plain
+    /// `attyvo read`; use `read_stdout_as` to read independently of it.
+    pub fn read_stdout(&self, name: &str) -> Result<Vec<u8>> {
+        self.read_stdout_as(name, DEFAULT_CONSUMER)
+    }
+
+    /// Returns the stdout the daemon logged since the previous read by `consumer`,
+    /// which each see all of the daemon's output, without waiting for more.
+    pub fn read_stdout_as(&self, name: &str, consumer: &str) -> Result<Vec<u8>> {
+        validate_consumer(consumer).map_err(anyhow::Error::msg)?;
+        let (output, _) = read_stdout(&self.dir, checked(name)?, consumer, None)?;
+        Ok(output)
+    }
+
+    /// Takes whatever stderr the daemon has buffered, without waiting for more.
+    pub fn read_stderr(&self, name: &str) -> Result<Vec<u8>> {
+        read_stderr(&self.dir, checked(name)?)
+    }
+
+    /// Changes the size of the daemon's terminal.
+    pub fn resize(&self, name: &str, rows: u16, cols: u16) -> Result<()> {
+        resize_daemon(&self.dir, checked(name)?, rows, cols)
+    }
+
+    /// Sends `signal`, e.g. `libc::SIGHUP`, to the daemon's command.
+    pub fn signal(&self, name: &str, signal: i32) -> Result<()> {
+        signal_daemon(&self.dir, checked(name)?, signal)
+    }
+
+    /// Freezes the daemon's command with SIGSTOP until `resume`.
+    pub fn pause(&self, name: &str) -> Result<()> {
+        pause_daemon(&self.dir, checked(name)?)
+    }
+
+    /// Lets a paused daemon's command carry on.
+    pub fn resume(&self, name: &str) -> Result<()> {
+        resume_daemon(&self.dir, checked(name)?)
+    }
+
+    /// Stops the daemon if it is running and starts it again with the same settings.
+    pub fn restart(&self, name: &str) -> Result<()> {
+        self.spawn(checked(name)?, None)
+    }
+
+    /// Gives daemon `old` the name `new` without restarting it.
+    pub fn rename(&self, old: &str, new: &str) -> Result<()> {
+        rename_daemon(&self.dir, checked(old)?, checked(new)?)
+    }
+
+    /// Stops the daemon's command, with SIGKILL if it outlives `grace` after
+    /// SIGTERM, and removes all of the daemon's files.
+    pub fn kill(&self, name: &str, grace: Duration) -> Result<KillOutcome> {
+        kill_daemon(&self.dir, checked(name)?, grace, false, false)
+    }
+
+    /// Blocks until the daemon's command has exited for good and returns its exit
+    /// status as a shell would report it, or `None` if `timeout` passed first.
+    pub fn wait(&self, name: &str, timeout: Option<Duration>) -> Result<Option<i32>> {
+        wait_daemon(&self.dir, checked(name)?, timeout)
+    }
+
+    /// The status of one daemon.
+    pub fn status(&self, name: &str) -> Result<DaemonStatus> {
+        daemon_status(&self.dir, checked(name)?)
+    }
+
+    /// The status of every daemon, sorted by name.
+    pub fn list(&self) -> Result<Vec<DaemonStatus>> {
+        list_daemon_statuses(&self.dir)
+    }
+
+    /// Has `attyvo spawn` start daemon `name` with `meta`, or restart it with
+    /// its recorded settings. Starting a daemon forks, which is only safe in a
+    /// process without other threads that may hold locks, so it happens in a
+    /// fresh process rather than in ours.
+    fn spawn(&self, name: &str, meta: Option<&DaemonMeta>) -> Result<()> {
+        let mut command = Command::new(&self.program);
+        command.arg("--dir").arg(&self.dir).args(["spawn", name]);
+        // The error it reports becomes ours, which has no use for its backtrace.
+        command.env("RUST_LIB_BACKTRACE", "0");
+        if meta.is_none() {
+            command.arg("--restart");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.program.display()))?;
+        if let (Some(meta), Some(mut stdin)) = (meta, child.stdin.take()) {
+            stdin.write_all(serde_json::to_string(meta)?.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Debug logging, if enabled, comes before the error.
+        let message = stderr
+            .split_once("Error: ")
+            .map_or(&*stderr, |(_, error)| error);
+        let message = message.trim().to_string();
+        let command = meta.map(|meta| meta.command.clone());
+        Err(match (output.status.code(), command) {
+            (Some(error::NOT_FOUND), _) => Failure::NotFound(name.to_string()).into(),
+            (Some(error::ALREADY_EXISTS), _) => Failure::AlreadyExists(name.to_string()).into(),
+            (Some(error::NOT_RESPONDING), _) => Failure::NotResponding(name.to_string()).into(),
+            (Some(error::NOT_EXECUTABLE), Some(command)) => Failure::NotExecutable(command).into(),
+            (Some(error::COMMAND_NOT_FOUND), Some(command)) => {
+                Failure::CommandNotFound(command).into()
+            }
+            _ => anyhow::Error::msg(message),
+        })
+    }
+}
+
+/// Rejects names that could reach outside the pipe directory, which the command
+/// line does while parsing its arguments.
+fn checked(name: &str) -> Result<&str> {
+    validate_name(name).map_err(anyhow::Error::msg)?;
+    Ok(name)
+}
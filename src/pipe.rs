@@ -0,0 +1,198 @@
+//! Relays that feed one daemon's output into another's input, for chaining
+//! managed daemons like a shell pipeline that outlives the shell.
+
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use daemonize_me::Daemon;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DaemonPaths, command_active, ensure_process_is_running, is_process_alive, load_meta,
+    logfile::{self, Cursor},
+    procfs, write, write_atomic,
+};
+
+/// How long `start` waits for a new relay to report in.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A background process copying everything daemon `src` prints into daemon `dst`.
+#[derive(Serialize, Deserialize)]
+pub struct Relay {
+    pub src: String,
+    pub dst: String,
+    pub pid: i32,
+    start_time: Option<u64>,
+}
+
+impl Relay {
+    /// Whether the relay's process is still the one that wrote its file.
+    fn alive(&self) -> bool {
+        is_process_alive(self.pid)
+            && (self.start_time.is_none() || procfs::start_time(self.pid) == self.start_time)
+    }
+}
+
+/// Where the relay from `src` into `dst` records itself.
+fn relay_path(dir: &Path, src: &str, dst: &str) -> PathBuf {
+    DaemonPaths::new(dir, src).relay(dst)
+}
+
+fn load(path: &Path) -> Option<Relay> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+/// Starts a detached relay that feeds the output `src` prints from now on into
+/// the input of `dst`, until either of them goes away or `stop` is called.
+pub fn start(dir: &Path, src: &str, dst: &str) -> Result<Relay> {
+    if src == dst {
+        return Err(anyhow::anyhow!("Cannot pipe daemon '{}' into itself", src));
+    }
+    ensure_process_is_running(dir, src)?;
+    ensure_process_is_running(dir, dst)?;
+    let path = relay_path(dir, src, dst);
+    if load(&path).is_some_and(|relay| relay.alive()) {
+        return Err(anyhow::anyhow!(
+            "Output of daemon '{}' is already piped into daemon '{}'",
+            src,
+            dst
+        ));
+    }
+    std::fs::remove_file(&path).ok();
+    // Only output from here on is relayed, not the history already in the log.
+    let stdout_log = DaemonPaths::new(dir, src).stdout_log;
+    let metadata = std::fs::metadata(&stdout_log)?;
+    let cursor = Cursor {
+        ino: metadata.ino(),
+        pos: metadata.len(),
+    };
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error().into()),
+        // Like a daemon, the child must never return into the caller.
+        0 => std::process::exit(match detach_and_relay(dir, src, dst, &path, cursor) {
+            Ok(()) => 0,
+            Err(_) => 1,
+        }),
+        child => {
+            let mut status = 0;
+            unsafe { libc::waitpid(child, &mut status, 0) };
+        }
+    }
+    let deadline = Instant::now() + START_TIMEOUT;
+    loop {
+        if let Some(relay) = load(&path) {
+            return Ok(relay);
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow::anyhow!(
+                "Relay from daemon '{}' into daemon '{}' did not start",
+                src,
+                dst
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn detach_and_relay(dir: &Path, src: &str, dst: &str, path: &Path, cursor: Cursor) -> Result<()> {
+    Daemon::new().work_dir(".").start()?;
+    let pid = std::process::id() as i32;
+    let relay = Relay {
+        src: src.to_string(),
+        dst: dst.to_string(),
+        pid,
+        start_time: procfs::start_time(pid),
+    };
+    write_atomic(path, serde_json::to_string(&relay)?.as_bytes())?;
+    let result = relay_output(dir, src, dst, path, cursor);
+    // A newer relay may have taken over the file after `stop`.
+    if load(path).is_some_and(|current| current.pid == pid) {
+        std::fs::remove_file(path).ok();
+    }
+    result
+}
+
+/// Copies new output of `src` into `dst` while both are in use and the relay
+/// file at `path` is still there. A daemon that is restarting counts as in use,
+/// and output it could not take yet is offered again once it is back. A PTY
+/// turns every newline into `\r\n`, which would reach `dst` as two line ends,
+/// so from a PTY the `\r` is dropped again.
+fn relay_output(dir: &Path, src: &str, dst: &str, path: &Path, mut cursor: Cursor) -> Result<()> {
+    let paths = DaemonPaths::new(dir, src);
+    let on_pty = !load_meta(&paths).is_ok_and(|meta| meta.no_pty);
+    while path.exists() && command_active(dir, src) && command_active(dir, dst) {
+        let (mut output, next) = logfile::read_after(&paths.stdout_log, Some(cursor), None)?;
+        if on_pty {
+            output = strip_carriage_returns(&output);
+        }
+        if output.is_empty() || write(dir, dst, &output, false).is_ok() {
+            cursor = next;
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    Ok(())
+}
+
+/// Stops the relay from `src` into `dst`.
+pub fn stop(dir: &Path, src: &str, dst: &str) -> Result<()> {
+    let path = relay_path(dir, src, dst);
+    let Some(relay) = load(&path) else {
+        return Err(anyhow::anyhow!(
+            "Output of daemon '{}' is not piped into daemon '{}'",
+            src,
+            dst
+        ));
+    };
+    std::fs::remove_file(&path)?;
+    if relay.alive() {
+        unsafe { libc::kill(relay.pid, libc::SIGTERM) };
+    }
+    Ok(())
+}
+
+/// Every relay that is still running, sorted by source and destination. The
+/// files of relays that were killed are removed on the way.
+pub fn list(dir: &Path) -> Result<Vec<Relay>> {
+    let mut relays = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "relay") {
+            continue;
+        }
+        match load(&path) {
+            Some(relay) if relay.alive() => relays.push(relay),
+            _ => {
+                std::fs::remove_file(&path).ok();
+            }
+        }
+    }
+    relays.sort_by(|a, b| (&a.src, &a.dst).cmp(&(&b.src, &b.dst)));
+    Ok(relays)
+}
+
+fn strip_carriage_returns(output: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(output.len());
+    for (i, &b) in output.iter().enumerate() {
+        if !(b == b'\r' && output.get(i + 1) == Some(&b'\n')) {
+            stripped.push(b);
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_carriage_returns_before_newlines_only() {
+        assert_eq!(strip_carriage_returns(b"one\r\ntwo\r\n"), b"one\ntwo\n");
+        assert_eq!(strip_carriage_returns(b"50%\r100%\r\n"), b"50%\r100%\n");
+        assert_eq!(strip_carriage_returns(b"trailing\r"), b"trailing\r");
+    }
+}
@@ -0,0 +1,59 @@
+//! Per-process resource usage read from Linux's `/proc`.
+
+use std::path::{Path, PathBuf};
+
+/// A snapshot of a process's cumulative CPU time and current memory use.
+#[derive(Clone, Copy)]
+pub struct Usage {
+    /// User plus system CPU time, in clock ticks.
+    pub cpu_ticks: u64,
+    /// Resident set size in KiB.
+    pub rss_kib: u64,
+}
+
+/// Whether `/proc` is there to be read at all.
+pub fn available() -> bool {
+    Path::new("/proc/self/stat").exists()
+}
+
+/// Reads the current usage of `pid`, or `None` if it is gone or unreadable.
+pub fn sample(pid: i32) -> Option<Usage> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is parenthesised and may contain spaces, so count fields
+    // from the closing parenthesis: utime and stime are the 12th and 13th after it.
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let rss_kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.split_whitespace().next()?.parse().ok())
+        .unwrap_or(0);
+    Some(Usage {
+        cpu_ticks: utime + stime,
+        rss_kib,
+    })
+}
+
+/// When `pid` started, in clock ticks since boot, or `None` if it is gone or
+/// unreadable. Together with the PID this identifies a process for good.
+pub fn start_time(pid: i32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // starttime is field 22, the 20th after the parenthesised command name.
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    fields.get(19)?.parse().ok()
+}
+
+/// The current working directory of `pid`, or `None` if it is gone or unreadable.
+pub fn cwd(pid: i32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+/// Number of clock ticks per second that `cpu_ticks` is measured in.
+pub fn ticks_per_second() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
@@ -0,0 +1,68 @@
+//! In-memory buffering of a daemon's output between its command and its FIFOs.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Write},
+    os::fd::AsRawFd,
+    sync::{Condvar, Mutex},
+};
+
+/// A bounded buffer that the relays fill and a pump thread drains into a FIFO
+/// whenever the pipe has room. Once `capacity` is reached the oldest bytes are
+/// dropped, so the command never waits on a reader that is not there.
+pub struct RingBuffer {
+    data: Mutex<VecDeque<u8>>,
+    capacity: usize,
+    available: Condvar,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            data: Mutex::new(VecDeque::new()),
+            capacity,
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, buf: &[u8]) {
+        let mut data = self.data.lock().unwrap();
+        let buf = &buf[buf.len().saturating_sub(self.capacity)..];
+        let overflow = (data.len() + buf.len()).saturating_sub(self.capacity);
+        data.drain(..overflow);
+        data.extend(buf);
+        self.available.notify_one();
+    }
+
+    /// Moves buffered bytes into `fifo`, which must be non-blocking, forever.
+    pub fn pump(&self, mut fifo: File) {
+        loop {
+            let mut data = self.data.lock().unwrap();
+            while data.is_empty() {
+                data = self.available.wait(data).unwrap();
+            }
+            match fifo.write(data.as_slices().0) {
+                Ok(n) => {
+                    data.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // The pipe is full; let the relays keep pushing while we wait.
+                    drop(data);
+                    wait_writable(&fifo);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+fn wait_writable(file: &File) {
+    let mut fds = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, -1) };
+}
@@ -0,0 +1,105 @@
+//! Request/response exchanges with interactive daemons such as REPLs.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use regex::Regex;
+
+use crate::{
+    DaemonPaths, DirChanges, ansi::AnsiStripper, deadline_after, drain_fifo,
+    ensure_process_is_running, logfile, write,
+};
+
+/// How long `send` waits for more output by default before calling a response complete.
+pub const DEFAULT_IDLE: Duration = Duration::from_millis(500);
+
+/// When the output following a message counts as its complete response.
+pub struct ResponseEnd {
+    /// Stop once no new output has arrived for this long.
+    pub idle: Option<Duration>,
+    /// Stop as soon as the response, with ANSI sequences removed, matches this.
+    pub until: Option<Regex>,
+    /// Give up after this long in total.
+    pub timeout: Option<Duration>,
+}
+
+/// Writes `message` to the daemon's stdin and collects the output it produces,
+/// discarding whatever was already waiting in the pipe. The flag tells whether
+/// the response was cut short by the timeout.
+pub fn send(
+    dir: &Path,
+    name: &str,
+    message: &[u8],
+    newline: bool,
+    end: &ResponseEnd,
+) -> Result<(Vec<u8>, bool)> {
+    ensure_process_is_running(dir, name)?;
+    let paths = DaemonPaths::new(dir, name);
+    let read_log_error =
+        |e: std::io::Error| anyhow::anyhow!("Cannot read stdout log of daemon '{}': {}", name, e);
+    // The daemon may still hold older output in its buffer that reaches the
+    // pipe only after we drained it, so the response is taken from the log,
+    // starting where it ended before the message was written.
+    let mut cursor = logfile::end_cursor(&paths.stdout_log).map_err(read_log_error)?;
+    let changes = DirChanges::watch(dir)?;
+    drain_fifo(name, &paths.stdout, None)?;
+    write(dir, name, message, newline)?;
+
+    let deadline = end.timeout.and_then(deadline_after);
+    let mut idle_since = Instant::now();
+    let mut response = Vec::new();
+    let mut stripper = AnsiStripper::default();
+    let mut text = String::new();
+    loop {
+        // Check first so that whatever was logged right before the exit is still seen.
+        let running = ensure_process_is_running(dir, name).is_ok();
+        let (output, next) =
+            logfile::read_after(&paths.stdout_log, Some(cursor), None).map_err(read_log_error)?;
+        cursor = next;
+        if !output.is_empty() {
+            idle_since = Instant::now();
+            response.extend_from_slice(&output);
+            if let Some(until) = &end.until {
+                text.push_str(&String::from_utf8_lossy(&stripper.strip(&output)));
+                if until.is_match(&text) {
+                    break;
+                }
+            }
+        }
+        if !running {
+            // No more output is coming.
+            break;
+        }
+
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            discard_piped_response(name, &paths)?;
+            return Ok((response, true));
+        }
+        // Measured from the last output, so a huge --idle must not overflow either.
+        let quiet = end.idle.map(|idle| idle.saturating_sub(now - idle_since));
+        if quiet.is_some_and(|quiet| quiet.is_zero()) {
+            break;
+        }
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(now));
+        let wait = match (quiet, remaining) {
+            (Some(quiet), Some(remaining)) => Some(quiet.min(remaining)),
+            (quiet, remaining) => quiet.or(remaining),
+        };
+        changes.wait(wait)?;
+    }
+    discard_piped_response(name, &paths)?;
+    Ok((response, false))
+}
+
+/// Drops the response from the pipe as well, so that a later `read` does not
+/// return it again.
+fn discard_piped_response(name: &str, paths: &DaemonPaths) -> Result<()> {
+    if paths.stdout.exists() {
+        drain_fifo(name, &paths.stdout, None)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Restarting a daemon whenever files it depends on change, for edit-and-rerun
+//! development loops.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::{DEFAULT_READY_TIMEOUT, restart_daemon};
+
+/// How long `watch` waits for changes to stop arriving by default before restarting.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Which changes under the watched paths call for a restart.
+pub struct Filter {
+    /// File extensions, without the dot, that count; empty means every file.
+    pub extensions: Vec<String>,
+    /// Changes in here, such as the daemon's own logs, never count.
+    pub ignore: PathBuf,
+}
+
+impl Filter {
+    fn matches(&self, path: &Path) -> bool {
+        if path.starts_with(&self.ignore) {
+            return false;
+        }
+        self.extensions.is_empty()
+            || path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| self.extensions.iter().any(|wanted| wanted == ext))
+    }
+}
+
+/// Watches `roots` recursively and restarts daemon `name` once matching changes
+/// have settled for `debounce`, handing the changed files and the outcome of
+/// each restart to `on_restart`. Runs until the watcher fails or `on_restart`
+/// returns an error.
+pub fn watch(
+    dir: &Path,
+    name: &str,
+    roots: &[PathBuf],
+    filter: &Filter,
+    debounce: Duration,
+    mut on_restart: impl FnMut(&[PathBuf], Result<()>) -> Result<()>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for root in roots {
+        // Absolute roots give absolute event paths, comparable with `filter.ignore`.
+        watcher
+            .watch(&std::path::absolute(root)?, RecursiveMode::Recursive)
+            .map_err(|e| anyhow::anyhow!("Cannot watch {}: {}", root.display(), e))?;
+    }
+
+    let changed_paths = |event: notify::Result<notify::Event>| -> Result<Vec<PathBuf>> {
+        let event = event?;
+        // Merely opening or reading a file is not a change.
+        if matches!(event.kind, EventKind::Access(_)) {
+            return Ok(Vec::new());
+        }
+        Ok(event
+            .paths
+            .into_iter()
+            .filter(|path| filter.matches(path))
+            .collect())
+    };
+    loop {
+        let Ok(event) = rx.recv() else {
+            return Ok(());
+        };
+        let mut changed = changed_paths(event)?;
+        if changed.is_empty() {
+            continue;
+        }
+        // Editors and build tools tend to touch several files in a burst.
+        while let Ok(event) = rx.recv_timeout(debounce) {
+            for path in changed_paths(event)? {
+                if !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+        log::debug!(
+            "Restarting daemon '{}' after changes to {:?}",
+            name,
+            changed
+        );
+        let result = restart_daemon(dir, name, Some(DEFAULT_READY_TIMEOUT));
+        on_restart(&changed, result)?;
+    }
+}
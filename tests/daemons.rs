@@ -0,0 +1,262 @@
+//! Runs the `attyvo` binary against a pipe directory of its own per test.
+
+use std::{
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::{Command, Output},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A pipe directory whose daemons are killed, and which is removed, on drop.
+struct PipeDir {
+    dir: PathBuf,
+}
+
+impl PipeDir {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("attyvo-test-{}-{}", std::process::id(), test));
+        std::fs::remove_dir_all(&dir).ok();
+        PipeDir { dir }
+    }
+
+    fn attyvo(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_attyvo"))
+            .arg("--dir")
+            .arg(&self.dir)
+            .args(args)
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap()
+    }
+
+    /// Runs attyvo with `args`, expecting it to succeed, and returns its stdout.
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.attyvo(args);
+        assert!(
+            output.status.success(),
+            "attyvo {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// The state `status` reports for daemon `name`, if it exists yet.
+    fn state(&self, name: &str) -> Option<String> {
+        let output = self.attyvo(&["--json", "status", name]);
+        let status: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+        Some(status["state"].as_str()?.to_string())
+    }
+
+    /// Waits up to ten seconds for daemon `name` to be in `state`. Without
+    /// `--wait-ready`, `create` returns before the daemon has written its files.
+    fn wait_for_state(&self, name: &str, state: &str) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while self.state(name).as_deref() != Some(state) {
+            assert!(
+                Instant::now() < deadline,
+                "daemon '{}' never got {}",
+                name,
+                state
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for PipeDir {
+    fn drop(&mut self) {
+        self.attyvo(&["kill-all", "--timeout", "1"]);
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+#[test]
+fn resize_changes_the_terminal_size_the_command_sees() {
+    let pipes = PipeDir::new("resize");
+    pipes.ok(&[
+        "create",
+        "--wait-ready",
+        "--rows",
+        "24",
+        "--cols",
+        "80",
+        "term",
+        "sh",
+    ]);
+    assert_eq!(pipes.state("term").as_deref(), Some("running"));
+    pipes.ok(&["resize", "term", "40", "100"]);
+    pipes.ok(&["write", "term", "stty size"]);
+    pipes.ok(&["wait-output", "--timeout", "10", "term", "^40 100"]);
+}
+
+#[test]
+fn prune_keeps_a_daemon_waiting_to_restart() {
+    let pipes = PipeDir::new("prune");
+    pipes.ok(&[
+        "create",
+        "--restart",
+        "always",
+        "--restart-delay",
+        "5",
+        "svc",
+        "--",
+        "sh",
+        "-c",
+        "exit 1",
+    ]);
+    pipes.wait_for_state("svc", "restarting");
+    pipes.ok(&["prune"]);
+    assert_eq!(pipes.state("svc").as_deref(), Some("restarting"));
+}
+
+#[test]
+fn names_cannot_collide_with_other_daemons_files() {
+    let pipes = PipeDir::new("names");
+    let output = pipes.attyvo(&["create", "a.stdout", "cat"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid character '.'"));
+
+    pipes.ok(&["create", "--wait-ready", "a_stdin", "cat"]);
+    pipes.attyvo(&["create", "--wait-ready", "a", "true"]);
+    pipes.wait_for_state("a", "exited with code 0");
+    assert!(pipes.ok(&["clean"]).contains("daemon 'a'"));
+    assert_eq!(pipes.attyvo(&["status", "a"]).status.code(), Some(3));
+    assert_eq!(pipes.state("a_stdin").as_deref(), Some("running"));
+    pipes.ok(&["write", "a_stdin", "still here"]);
+    pipes.ok(&["wait-output", "--timeout", "10", "a_stdin", "still here"]);
+}
+
+#[test]
+fn create_write_read_kill() {
+    let pipes = PipeDir::new("lifecycle");
+    pipes.ok(&["create", "--wait-ready", "echo", "cat"]);
+    assert_eq!(pipes.state("echo").as_deref(), Some("running"));
+    pipes.ok(&["write", "echo", "hello"]);
+    pipes.ok(&["wait-output", "--timeout", "10", "echo", "hello"]);
+    assert!(pipes.ok(&["read", "echo"]).contains("hello"));
+    pipes.ok(&["kill", "echo"]);
+    // Exit code 3 means the daemon does not exist.
+    assert_eq!(pipes.attyvo(&["status", "echo"]).status.code(), Some(3));
+}
+
+#[test]
+fn killed_supervisor_does_not_take_over_a_new_daemon() {
+    let pipes = PipeDir::new("takeover");
+    pipes.ok(&[
+        "create",
+        "--restart",
+        "always",
+        "--restart-delay",
+        "2",
+        "svc",
+        "--",
+        "sh",
+        "-c",
+        "exit 1",
+    ]);
+    pipes.wait_for_state("svc", "restarting");
+    pipes.ok(&["kill", "svc"]);
+    pipes.ok(&["create", "--wait-ready", "svc", "sleep", "30"]);
+    let status: serde_json::Value =
+        serde_json::from_str(&pipes.ok(&["--json", "status", "svc"])).unwrap();
+    // Past the old restart delay, the old supervisor would have restarted `sh` by now.
+    thread::sleep(Duration::from_secs(3));
+    let after: serde_json::Value =
+        serde_json::from_str(&pipes.ok(&["--json", "status", "svc"])).unwrap();
+    assert_eq!(after["state"], "running");
+    assert_eq!(after["pid"], status["pid"]);
+    assert_eq!(after["command"], status["command"]);
+}
+
+#[test]
+fn wait_accepts_a_timeout_too_long_for_a_deadline() {
+    let pipes = PipeDir::new("wait-timeout");
+    pipes.ok(&[
+        "create",
+        "--wait-ready",
+        "quits",
+        "--",
+        "sh",
+        "-c",
+        "sleep 1; exit 3",
+    ]);
+    let output = pipes.attyvo(&["wait", "--timeout", &u64::MAX.to_string(), "quits"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn events_reach_clients_of_the_socket() {
+    let pipes = PipeDir::new("events");
+    pipes.ok(&["create", "--wait-ready", "hub", "cat"]);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let socket = loop {
+        match UnixStream::connect(pipes.dir.join("events.sock")) {
+            Ok(socket) => break socket,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("cannot connect to the events socket: {}", e),
+        }
+    };
+    socket
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    pipes.attyvo(&["create", "--wait-ready", "quits", "true"]);
+    let events: Vec<serde_json::Value> = BufReader::new(socket)
+        .lines()
+        .take(2)
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert_eq!(events[0]["event"], "created");
+    assert_eq!(events[0]["name"], "quits");
+    assert_eq!(events[1]["event"], "exited");
+    assert_eq!(events[1]["code"], 0);
+}
+
+#[test]
+fn status_reports_a_daemon_killed_with_its_logs_kept() {
+    let pipes = PipeDir::new("keep-logs");
+    pipes.ok(&["create", "--wait-ready", "kept", "cat"]);
+    pipes.ok(&["write", "kept", "evidence"]);
+    pipes.ok(&["wait-output", "--timeout", "10", "kept", "evidence"]);
+    pipes.ok(&["kill", "--keep-logs", "kept"]);
+    assert_eq!(pipes.state("kept").as_deref(), Some("killed"));
+    assert!(pipes.ok(&["logs", "kept"]).contains("evidence"));
+}
+
+#[test]
+fn clean_removes_the_files_kept_by_kill() {
+    let pipes = PipeDir::new("clean");
+    pipes.ok(&["create", "--wait-ready", "src", "cat"]);
+    pipes.ok(&["create", "--wait-ready", "dst", "cat"]);
+    pipes.ok(&["pipe", "src", "dst"]);
+    assert!(pipes.dir.join("src.dst.relay").exists());
+    pipes.ok(&["kill", "--keep-logs", "src"]);
+    assert!(pipes.ok(&["clean", "src"]).contains("daemon 'src'"));
+    assert_eq!(pipes.attyvo(&["status", "src"]).status.code(), Some(3));
+    let leftovers: Vec<_> = std::fs::read_dir(&pipes.dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|file| file.starts_with("src."))
+        .collect();
+    assert!(leftovers.is_empty(), "left behind: {:?}", leftovers);
+}
+
+#[test]
+fn manager_starts_daemons_with_the_program_it_is_given() {
+    let pipes = PipeDir::new("manager");
+    let daemons = attyvo::DaemonManager::new(&pipes.dir, env!("CARGO_BIN_EXE_attyvo"));
+    let meta = attyvo::DaemonMeta::new("cat".into(), Vec::new());
+    daemons.create("managed", &meta).unwrap();
+    daemons.write("managed", b"from the library\n").unwrap();
+    pipes.ok(&[
+        "wait-output",
+        "--timeout",
+        "10",
+        "managed",
+        "from the library",
+    ]);
+    daemons.kill("managed", Duration::from_secs(5)).unwrap();
+    assert_eq!(pipes.state("managed"), None);
+}